@@ -0,0 +1,527 @@
+use std::env;
+use std::env::VarError;
+use std::fs;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde::Deserialize;
+
+use crate::logs::{LogFileKind, LogSource, REGEX_PATTERN_PREFIX, StartPosition, WatcherBackend, WatcherSettings};
+use crate::logs::access_log_format::AccessLogFormat;
+use crate::metrics;
+use crate::metrics::MetricsConfiguration;
+use crate::route_rules::RouteRules;
+
+const HTTP_HOST: &str = "HTTP_HOST";
+const METRICS_PATH: &str = "METRICS_PATH";
+const ACCESS_LOG_FILE_PATTERN: &str = "ACCESS_LOG_FILE_PATTERN";
+const ERROR_LOG_FILE_PATTERN: &str = "ERROR_LOG_FILE_PATTERN";
+const ACCESS_LOG_FORMAT: &str = "ACCESS_LOG_FORMAT";
+const REQUEST_DURATION_BUCKETS: &str = "REQUEST_DURATION_BUCKETS";
+const RESPONSE_SIZE_BUCKETS: &str = "RESPONSE_SIZE_BUCKETS";
+const ERROR_CODES: &str = "ERROR_CODES";
+const ROUTE_RULES: &str = "ROUTE_RULES";
+const MAX_SERIES_PER_METRIC: &str = "MAX_SERIES_PER_METRIC";
+const SERIES_TTL_SECONDS: &str = "SERIES_TTL_SECONDS";
+const RESCAN_INTERVAL_SECONDS: &str = "RESCAN_INTERVAL_SECONDS";
+const STATE_FILE: &str = "STATE_FILE";
+const ROTATION_GRACE_PERIOD_SECONDS: &str = "ROTATION_GRACE_PERIOD_SECONDS";
+const WATCHER_BACKEND: &str = "WATCHER_BACKEND";
+const POLL_INTERVAL_MILLISECONDS: &str = "POLL_INTERVAL_MILLISECONDS";
+const START_POSITION: &str = "START_POSITION";
+
+const DEFAULT_HOST: &str = "127.0.0.1";
+const DEFAULT_PORT: u16 = 9240;
+const DEFAULT_METRICS_PATH: &str = "/metrics";
+const DEFAULT_ACCESS_LOG_FORMAT: &str = "prometheus";
+
+pub struct Configuration {
+	pub listen_addresses: Vec<SocketAddr>,
+	pub metrics_path: String,
+	pub log_sources: Vec<LogSource>,
+	pub metrics: MetricsConfiguration,
+	pub watcher: WatcherSettings,
+}
+
+impl Configuration {
+	/// Reads the configuration from a TOML file.
+	pub fn from_file(path: &Path) -> Result<Configuration> {
+		let contents = fs::read_to_string(path).with_context(|| format!("Could not read configuration file: {}", path.display()))?;
+		Self::from_toml(&contents).with_context(|| format!("Invalid configuration file: {}", path.display()))
+	}
+	
+	fn from_toml(contents: &str) -> Result<Configuration> {
+		let file = toml::from_str::<ConfigurationFile>(contents)?;
+		
+		let listen_addresses = match (file.listen.addresses, file.listen.host) {
+			(Some(_), Some(_)) => bail!("The deprecated [listen] host setting cannot be combined with addresses"),
+			(Some(addresses), None) => addresses.iter().map(|address| parse_listen_address(address)).collect::<Result<Vec<_>>>()?,
+			(None, Some(host)) => {
+				println!("The [listen] host setting is deprecated, use addresses instead.");
+				vec![parse_listen_address(&host)?]
+			}
+			(None, None) => vec![parse_listen_address(DEFAULT_HOST)?],
+		};
+		
+		if listen_addresses.is_empty() {
+			bail!("No listen addresses configured");
+		}
+		
+		let metrics_path = parse_metrics_path(file.listen.metrics_path.as_deref().unwrap_or(DEFAULT_METRICS_PATH))?;
+		let mut log_sources = Vec::new();
+		
+		for access_log in file.access_logs {
+			let format = access_log.format.as_deref().unwrap_or(DEFAULT_ACCESS_LOG_FORMAT);
+			let format = AccessLogFormat::from_preset_or_format(format).with_context(|| format!("Invalid access log format: {}", format))?;
+			let start_position = parse_start_position(access_log.start_position.as_deref())?;
+			log_sources.push(LogSource { pattern: access_log.pattern, label: access_log.label, kind: LogFileKind::Access(Arc::new(format)), start_position });
+		}
+		
+		for error_log in file.error_logs {
+			let start_position = parse_start_position(error_log.start_position.as_deref())?;
+			log_sources.push(LogSource { pattern: error_log.pattern, label: error_log.label, kind: LogFileKind::Error, start_position });
+		}
+		
+		if log_sources.is_empty() {
+			bail!("No access or error logs configured");
+		}
+		
+		let mut metrics = MetricsConfiguration::default();
+		let metrics_section = file.metrics;
+		
+		if let Some(buckets) = metrics_section.request_duration_buckets {
+			metrics.request_duration_buckets = metrics::validate_histogram_buckets(buckets).context("Invalid request_duration_buckets")?;
+		}
+		
+		if let Some(buckets) = metrics_section.response_size_buckets {
+			metrics.response_size_buckets = Some(metrics::validate_histogram_buckets(buckets).context("Invalid response_size_buckets")?);
+		}
+		
+		if let Some(codes) = metrics_section.error_codes {
+			metrics.error_code_allowlist = Some(metrics::validate_error_code_allowlist(codes.iter().map(String::as_str)).context("Invalid error_codes")?);
+		}
+		
+		if let Some(routes) = metrics_section.routes {
+			let mut rules = RouteRules::new();
+			for route in routes {
+				rules.add(&route.pattern, &route.route)?;
+			}
+			metrics.route_rules = Some(rules);
+		}
+		
+		if let Some(max_series) = metrics_section.max_series_per_metric {
+			metrics.max_series_per_metric = max_series;
+		}
+		
+		if let Some(ttl) = metrics_section.series_ttl_seconds {
+			metrics.series_ttl = Some(parse_series_ttl(ttl)?);
+		}
+		
+		let mut watcher = WatcherSettings::default();
+		
+		if let Some(interval) = file.watcher.rescan_interval_seconds {
+			watcher.rescan_interval = parse_optional_interval(interval);
+		}
+		
+		watcher.state_file = file.watcher.state_file;
+		
+		if let Some(grace_period) = file.watcher.rotation_grace_period_seconds {
+			watcher.rotation_grace_period = parse_optional_interval(grace_period);
+		}
+		
+		if let Some(backend) = &file.watcher.backend {
+			watcher.backend = parse_watcher_backend(backend)?;
+		}
+		
+		if let Some(poll_interval) = file.watcher.poll_interval_milliseconds {
+			watcher.poll_interval = parse_poll_interval(poll_interval)?;
+		}
+		
+		Ok(Configuration { listen_addresses, metrics_path, log_sources, metrics, watcher })
+	}
+	
+	/// Reads the configuration from environment variables, which support one access log pattern and one error log pattern.
+	pub fn from_env() -> Result<Configuration> {
+		let listen_addresses = env::var(HTTP_HOST).unwrap_or(String::from(DEFAULT_HOST));
+		let listen_addresses = listen_addresses.split(',').map(|address| parse_listen_address(address.trim())).collect::<Result<Vec<_>>>()?;
+		let metrics_path = parse_metrics_path(&env::var(METRICS_PATH).unwrap_or(String::from(DEFAULT_METRICS_PATH)))?;
+		
+		let access_log_format = env::var(ACCESS_LOG_FORMAT).unwrap_or(String::from(DEFAULT_ACCESS_LOG_FORMAT));
+		let access_log_format = AccessLogFormat::from_preset_or_format(&access_log_format).with_context(|| format!("Invalid access log format: {}", access_log_format))?;
+		
+		let access_log_kind = LogFileKind::Access(Arc::new(access_log_format));
+		let start_position = parse_start_position(env::var(START_POSITION).ok().as_deref()).with_context(|| format!("Invalid {}", START_POSITION))?;
+		let mut log_sources = Vec::new();
+		
+		for pattern in split_patterns(&read_required_env(ACCESS_LOG_FILE_PATTERN)?) {
+			log_sources.push(LogSource { pattern, label: None, kind: access_log_kind.clone(), start_position });
+		}
+		
+		for pattern in split_patterns(&read_required_env(ERROR_LOG_FILE_PATTERN)?) {
+			log_sources.push(LogSource { pattern, label: None, kind: LogFileKind::Error, start_position });
+		}
+		
+		let mut metrics = MetricsConfiguration::default();
+		
+		if let Ok(buckets) = env::var(REQUEST_DURATION_BUCKETS) {
+			metrics.request_duration_buckets = metrics::parse_histogram_buckets(&buckets).with_context(|| format!("Invalid {}", REQUEST_DURATION_BUCKETS))?;
+		}
+		
+		if let Ok(buckets) = env::var(RESPONSE_SIZE_BUCKETS) {
+			metrics.response_size_buckets = Some(metrics::parse_histogram_buckets(&buckets).with_context(|| format!("Invalid {}", RESPONSE_SIZE_BUCKETS))?);
+		}
+		
+		if let Ok(codes) = env::var(ERROR_CODES) {
+			metrics.error_code_allowlist = Some(metrics::parse_error_code_allowlist(&codes).with_context(|| format!("Invalid {}", ERROR_CODES))?);
+		}
+		
+		if let Ok(rules) = env::var(ROUTE_RULES) {
+			metrics.route_rules = Some(RouteRules::parse(&rules).with_context(|| format!("Invalid {}", ROUTE_RULES))?);
+		}
+		
+		if let Ok(max_series) = env::var(MAX_SERIES_PER_METRIC) {
+			metrics.max_series_per_metric = max_series.parse().map_err(|_| anyhow!("Invalid {}: {}", MAX_SERIES_PER_METRIC, max_series))?;
+		}
+		
+		if let Ok(ttl) = env::var(SERIES_TTL_SECONDS) {
+			let ttl = ttl.parse::<u64>().map_err(|_| anyhow!("Invalid {}: {}", SERIES_TTL_SECONDS, ttl))?;
+			metrics.series_ttl = Some(parse_series_ttl(ttl)?);
+		}
+		
+		let mut watcher = WatcherSettings::default();
+		
+		if let Ok(interval) = env::var(RESCAN_INTERVAL_SECONDS) {
+			let interval = interval.parse::<u64>().map_err(|_| anyhow!("Invalid {}: {}", RESCAN_INTERVAL_SECONDS, interval))?;
+			watcher.rescan_interval = parse_optional_interval(interval);
+		}
+		
+		if let Ok(state_file) = env::var(STATE_FILE) {
+			watcher.state_file = Some(PathBuf::from(state_file));
+		}
+		
+		if let Ok(grace_period) = env::var(ROTATION_GRACE_PERIOD_SECONDS) {
+			let grace_period = grace_period.parse::<u64>().map_err(|_| anyhow!("Invalid {}: {}", ROTATION_GRACE_PERIOD_SECONDS, grace_period))?;
+			watcher.rotation_grace_period = parse_optional_interval(grace_period);
+		}
+		
+		if let Ok(backend) = env::var(WATCHER_BACKEND) {
+			watcher.backend = parse_watcher_backend(&backend).with_context(|| format!("Invalid {}", WATCHER_BACKEND))?;
+		}
+		
+		if let Ok(poll_interval) = env::var(POLL_INTERVAL_MILLISECONDS) {
+			let poll_interval = poll_interval.parse::<u64>().map_err(|_| anyhow!("Invalid {}: {}", POLL_INTERVAL_MILLISECONDS, poll_interval))?;
+			watcher.poll_interval = parse_poll_interval(poll_interval)?;
+		}
+		
+		Ok(Configuration { listen_addresses, metrics_path, log_sources, metrics, watcher })
+	}
+}
+
+fn read_required_env(name: &str) -> Result<String> {
+	env::var(name).map_err(|err| match err {
+		VarError::NotPresent => anyhow!("Environment variable {} must be set", name),
+		VarError::NotUnicode(_) => anyhow!("Environment variable {} contains invalid characters", name)
+	})
+}
+
+/// Splits a list of file patterns separated by commas or newlines. Regular expression patterns may contain commas
+/// (e.g. in `{1,3}`), so they extend to the end of their line.
+fn split_patterns(patterns: &str) -> Vec<String> {
+	let mut result = Vec::new();
+	
+	for line in patterns.lines() {
+		let mut rest = line.trim();
+		
+		while !rest.is_empty() {
+			if rest.starts_with(REGEX_PATTERN_PREFIX) {
+				result.push(rest.to_string());
+				break;
+			}
+			
+			let (pattern, remainder) = rest.split_once(',').unwrap_or((rest, ""));
+			result.push(pattern.trim().to_string());
+			rest = remainder.trim_start();
+		}
+	}
+	
+	result
+}
+
+/// Parses an address in the form `ip:port` or `[ipv6]:port`. An address without a port uses the default port.
+fn parse_listen_address(address: &str) -> Result<SocketAddr> {
+	if let Ok(address) = SocketAddr::from_str(address) {
+		return Ok(address);
+	}
+	
+	IpAddr::from_str(address).map(|ip| SocketAddr::new(ip, DEFAULT_PORT)).map_err(|_| anyhow!("Invalid listen address: {}", address))
+}
+
+fn parse_metrics_path(path: &str) -> Result<String> {
+	if !path.starts_with('/') {
+		bail!("Metrics path must start with '/': {}", path);
+	}
+	
+	Ok(path.to_string())
+}
+
+fn parse_series_ttl(seconds: u64) -> Result<Duration> {
+	if seconds == 0 {
+		bail!("Series TTL must be greater than zero");
+	}
+	
+	Ok(Duration::from_secs(seconds))
+}
+
+/// Parses where to start reading log files found at startup, which defaults to the end.
+fn parse_start_position(position: Option<&str>) -> Result<StartPosition> {
+	match position {
+		None | Some("end") => Ok(StartPosition::End),
+		Some("beginning") => Ok(StartPosition::Beginning),
+		Some(position) => bail!("Invalid start position (expected \"beginning\" or \"end\"): {}", position),
+	}
+}
+
+fn parse_watcher_backend(backend: &str) -> Result<WatcherBackend> {
+	match backend {
+		"auto" => Ok(WatcherBackend::Auto),
+		"native" => Ok(WatcherBackend::Native),
+		"polling" => Ok(WatcherBackend::Polling),
+		_ => bail!("Invalid watcher backend (expected \"auto\", \"native\" or \"polling\"): {}", backend),
+	}
+}
+
+fn parse_poll_interval(milliseconds: u64) -> Result<Duration> {
+	if milliseconds == 0 {
+		bail!("Poll interval must be greater than zero");
+	}
+	
+	Ok(Duration::from_millis(milliseconds))
+}
+
+/// Parses an interval of a watcher feature, which is disabled by an interval of zero.
+fn parse_optional_interval(seconds: u64) -> Option<Duration> {
+	if seconds == 0 {
+		None
+	} else {
+		Some(Duration::from_secs(seconds))
+	}
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ConfigurationFile {
+	#[serde(default)]
+	listen: ListenSection,
+	#[serde(default)]
+	access_logs: Vec<AccessLogSection>,
+	#[serde(default)]
+	error_logs: Vec<ErrorLogSection>,
+	#[serde(default)]
+	metrics: MetricsSection,
+	#[serde(default)]
+	watcher: WatcherSection,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ListenSection {
+	addresses: Option<Vec<String>>,
+	/// Deprecated alias of a single listen address without a port.
+	host: Option<String>,
+	metrics_path: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct AccessLogSection {
+	pattern: String,
+	format: Option<String>,
+	label: Option<String>,
+	start_position: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ErrorLogSection {
+	pattern: String,
+	label: Option<String>,
+	start_position: Option<String>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MetricsSection {
+	request_duration_buckets: Option<Vec<f64>>,
+	response_size_buckets: Option<Vec<f64>>,
+	error_codes: Option<Vec<String>>,
+	routes: Option<Vec<RouteSection>>,
+	max_series_per_metric: Option<usize>,
+	series_ttl_seconds: Option<u64>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RouteSection {
+	pattern: String,
+	route: String,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct WatcherSection {
+	rescan_interval_seconds: Option<u64>,
+	state_file: Option<PathBuf>,
+	rotation_grace_period_seconds: Option<u64>,
+	backend: Option<String>,
+	poll_interval_milliseconds: Option<u64>,
+}
+
+#[cfg(test)]
+mod tests {
+	use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+	use std::path::PathBuf;
+	use std::time::Duration;
+	
+	use crate::logs::{LogFileKind, StartPosition, WatcherBackend};
+	
+	use super::{Configuration, parse_listen_address, split_patterns};
+	
+	#[test]
+	fn full_configuration() {
+		let config = Configuration::from_toml(r#"
+			[listen]
+			addresses = ["0.0.0.0:9100", "[::]:9100"]
+			metrics_path = "/apache/metrics"
+			
+			[[access_logs]]
+			pattern = "/var/log/apache2/*.access.log"
+			format = "combined"
+			
+			[[access_logs]]
+			pattern = "/srv/legacy/access.log"
+			format = "%h %l %u %t \"%r\" %>s %b"
+			label = "legacy"
+			
+			[[error_logs]]
+			pattern = "/var/log/apache2/*.error.log"
+			start_position = "beginning"
+			
+			[metrics]
+			request_duration_buckets = [0.1, 1, 10]
+			response_size_buckets = [1000, 100000]
+			error_codes = ["AH01630"]
+			routes = [{ pattern = "^/user/\\d+$", route = "/user/:id" }]
+			max_series_per_metric = 500
+			series_ttl_seconds = 3600
+			
+			[watcher]
+			rescan_interval_seconds = 30
+			state_file = "/var/lib/apache_prometheus_exporter/state.toml"
+			rotation_grace_period_seconds = 60
+			backend = "polling"
+			poll_interval_milliseconds = 250
+		"#).unwrap();
+		
+		assert_eq!(config.listen_addresses, vec![
+			SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 9100),
+			SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), 9100),
+		]);
+		assert_eq!(config.metrics_path, "/apache/metrics");
+		
+		assert_eq!(config.log_sources.len(), 3);
+		assert_eq!(config.log_sources[0].pattern, "/var/log/apache2/*.access.log");
+		assert_eq!(config.log_sources[0].label, None);
+		assert!(matches!(config.log_sources[0].kind, LogFileKind::Access(_)));
+		assert_eq!(config.log_sources[1].label, Some(String::from("legacy")));
+		assert_eq!(config.log_sources[1].start_position, StartPosition::End);
+		assert!(matches!(config.log_sources[2].kind, LogFileKind::Error));
+		assert_eq!(config.log_sources[2].start_position, StartPosition::Beginning);
+		
+		assert_eq!(config.metrics.request_duration_buckets, vec![0.1, 1.0, 10.0]);
+		assert_eq!(config.metrics.response_size_buckets, Some(vec![1000.0, 100000.0]));
+		assert!(config.metrics.error_code_allowlist.is_some_and(|codes| codes.contains("AH01630")));
+		assert!(config.metrics.route_rules.is_some_and(|rules| rules.route("/user/1") == "/user/:id"));
+		assert_eq!(config.metrics.max_series_per_metric, 500);
+		assert_eq!(config.metrics.series_ttl, Some(Duration::from_secs(3600)));
+		assert_eq!(config.watcher.rescan_interval, Some(Duration::from_secs(30)));
+		assert_eq!(config.watcher.state_file, Some(PathBuf::from("/var/lib/apache_prometheus_exporter/state.toml")));
+		assert_eq!(config.watcher.rotation_grace_period, Some(Duration::from_secs(60)));
+		assert_eq!(config.watcher.backend, WatcherBackend::Polling);
+		assert_eq!(config.watcher.poll_interval, Duration::from_millis(250));
+	}
+	
+	#[test]
+	fn minimal_configuration() {
+		let config = Configuration::from_toml(r#"
+			[[error_logs]]
+			pattern = "/var/log/apache2/error.log"
+		"#).unwrap();
+		
+		assert_eq!(config.listen_addresses, vec![SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 9240)]);
+		assert_eq!(config.metrics_path, "/metrics");
+		assert_eq!(config.log_sources.len(), 1);
+		assert!(config.metrics.route_rules.is_none());
+		assert_eq!(config.watcher.rescan_interval, Some(Duration::from_secs(10)));
+		assert_eq!(config.watcher.state_file, None);
+		assert_eq!(config.watcher.rotation_grace_period, Some(Duration::from_secs(10)));
+		assert_eq!(config.watcher.backend, WatcherBackend::Auto);
+	}
+	
+	#[test]
+	fn watcher_features_disabled() {
+		let config = Configuration::from_toml("[[error_logs]]\npattern = \"/error.log\"\n[watcher]\nrescan_interval_seconds = 0\nrotation_grace_period_seconds = 0").unwrap();
+		assert_eq!(config.watcher.rescan_interval, None);
+		assert_eq!(config.watcher.rotation_grace_period, None);
+	}
+	
+	#[test]
+	fn no_log_sources() {
+		assert!(matches!(Configuration::from_toml("[listen]"), Err(err) if err.to_string() == "No access or error logs configured"));
+	}
+	
+	#[test]
+	fn unknown_field() {
+		assert!(Configuration::from_toml("[[error_logs]]\npattern = \"/error.log\"\nformat = \"combined\"").is_err());
+	}
+	
+	#[test]
+	fn invalid_values() {
+		assert!(matches!(Configuration::from_toml("[listen]\naddresses = [\"localhost:80\"]\n[[error_logs]]\npattern = \"/error.log\""), Err(err) if err.to_string() == "Invalid listen address: localhost:80"));
+		assert!(matches!(Configuration::from_toml("[listen]\naddresses = []\n[[error_logs]]\npattern = \"/error.log\""), Err(err) if err.to_string() == "No listen addresses configured"));
+		assert!(matches!(Configuration::from_toml("[listen]\nmetrics_path = \"metrics\"\n[[error_logs]]\npattern = \"/error.log\""), Err(err) if err.to_string() == "Metrics path must start with '/': metrics"));
+		assert!(matches!(Configuration::from_toml("[[access_logs]]\npattern = \"/access.log\"\nformat = \"%h\""), Err(err) if err.to_string() == "Invalid access log format: %h"));
+		assert!(matches!(Configuration::from_toml("[[error_logs]]\npattern = \"/error.log\"\n[metrics]\nseries_ttl_seconds = 0"), Err(err) if err.to_string() == "Series TTL must be greater than zero"));
+		assert!(matches!(Configuration::from_toml("[[error_logs]]\npattern = \"/error.log\"\nstart_position = \"middle\""), Err(err) if err.to_string() == "Invalid start position (expected \"beginning\" or \"end\"): middle"));
+		assert!(matches!(Configuration::from_toml("[[error_logs]]\npattern = \"/error.log\"\n[watcher]\nbackend = \"inotify\""), Err(err) if err.to_string() == "Invalid watcher backend (expected \"auto\", \"native\" or \"polling\"): inotify"));
+		assert!(matches!(Configuration::from_toml("[[error_logs]]\npattern = \"/error.log\"\n[watcher]\npoll_interval_milliseconds = 0"), Err(err) if err.to_string() == "Poll interval must be greater than zero"));
+	}
+	
+	#[test]
+	fn deprecated_listen_host() {
+		let config = Configuration::from_toml("[listen]\nhost = \"0.0.0.0\"\n[[error_logs]]\npattern = \"/error.log\"").unwrap();
+		assert_eq!(config.listen_addresses, vec![SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 9240)]);
+		
+		assert!(matches!(Configuration::from_toml("[listen]\nhost = \"0.0.0.0\"\naddresses = [\"[::]:9240\"]\n[[error_logs]]\npattern = \"/error.log\""), Err(err) if err.to_string() == "The deprecated [listen] host setting cannot be combined with addresses"));
+	}
+	
+	#[test]
+	fn split_env_patterns() {
+		assert_eq!(split_patterns("/var/log/a.log, /var/log/b/*.log"), vec!["/var/log/a.log", "/var/log/b/*.log"]);
+		assert_eq!(split_patterns("/var/log/a.log,regex:/var/log/site[0-9]{1,3}/access\\.log\n/var/log/b.log"), vec![
+			"/var/log/a.log",
+			"regex:/var/log/site[0-9]{1,3}/access\\.log",
+			"/var/log/b.log",
+		]);
+	}
+	
+	#[test]
+	fn listen_address_without_port() {
+		assert_eq!(parse_listen_address("10.0.0.1").unwrap(), SocketAddr::new(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)), 9240));
+		assert_eq!(parse_listen_address("::1").unwrap(), SocketAddr::new(IpAddr::V6(Ipv6Addr::LOCALHOST), 9240));
+		assert_eq!(parse_listen_address("[::1]:80").unwrap(), SocketAddr::new(IpAddr::V6(Ipv6Addr::LOCALHOST), 80));
+	}
+}
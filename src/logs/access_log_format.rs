@@ -0,0 +1,338 @@
+use anyhow::{anyhow, bail, Result};
+
+/// The `prometheus` log format recommended in the README.
+pub const DEFAULT_ACCESS_LOG_FORMAT: &str = "%t %h \"%r\" %>s %O %{ms}T \"%{Referer}i\" \"%{User-Agent}i\"";
+
+/// Named log formats, as defined in the default Debian Apache configuration.
+const ACCESS_LOG_FORMAT_PRESETS: [(&str, &str); 4] = [
+	("prometheus", DEFAULT_ACCESS_LOG_FORMAT),
+	("common", "%h %l %u %t \"%r\" %>s %O"),
+	("combined", "%h %l %u %t \"%r\" %>s %O \"%{Referer}i\" \"%{User-Agent}i\""),
+	("vhost_combined", "%v:%p %h %l %u %t \"%r\" %>s %O \"%{Referer}i\" \"%{User-Agent}i\""),
+];
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum TimeUnit {
+	Seconds,
+	Milliseconds,
+	Microseconds,
+}
+
+impl TimeUnit {
+	pub fn to_seconds(self, value: u64) -> f64 {
+		match self {
+			TimeUnit::Seconds => value as f64,
+			TimeUnit::Milliseconds => value as f64 / 1_000.0,
+			TimeUnit::Microseconds => value as f64 / 1_000_000.0,
+		}
+	}
+}
+
+/// A single `mod_log_config` directive. Directives that the exporter does not use are still
+/// recognized, so that their values can be skipped when parsing a log line.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum AccessLogField {
+	/// `%h`
+	RemoteHost,
+	/// `%l`
+	RemoteLogname,
+	/// `%u`
+	RemoteUser,
+	/// `%t`
+	Time,
+	/// `%{format}t`
+	CustomTime,
+	/// `%r`
+	Request,
+	/// `%s`, `%>s`
+	Status,
+	/// `%b`
+	ResponseBytesClf,
+	/// `%O`
+	BytesSent,
+	/// `%I`
+	BytesReceived,
+	/// `%D`, `%T`, `%{s}T`, `%{ms}T`, `%{us}T`
+	ResponseTime(TimeUnit),
+	/// `%{X}i`
+	RequestHeader(String),
+	/// `%{X}o`
+	ResponseHeader(String),
+	/// `%v`
+	ServerName,
+	/// `%V`
+	RequestServerName,
+	/// `%p`
+	ServerPort,
+	/// `%k`
+	KeepAliveRequests,
+	/// `%X`
+	ConnectionStatus,
+	/// `%L`
+	LogId,
+	/// Any other directive, such as `%a` or `%{X}^ti`, whose value is skipped.
+	Other(String),
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum AccessLogFormatElement {
+	Literal(String),
+	Field(AccessLogField),
+}
+
+#[derive(Debug)]
+pub struct AccessLogFormat {
+	pub(super) elements: Vec<AccessLogFormatElement>,
+}
+
+impl AccessLogFormat {
+	/// Compiles a named preset (`prometheus`, `common`, `combined`, `vhost_combined`), or a custom `LogFormat` string.
+	pub fn from_preset_or_format(preset_or_format: &str) -> Result<AccessLogFormat> {
+		let preset = ACCESS_LOG_FORMAT_PRESETS.iter().find(|(name, _)| *name == preset_or_format.trim());
+		Self::compile(preset.map_or(preset_or_format, |(_, format)| format))
+	}
+	
+	/// Compiles an Apache `LogFormat` string.
+	///
+	/// Every field must be followed by text that separates it from the next field, with the exception of `%t`,
+	/// which is enclosed in brackets. The format must contain `%s` or `%>s`, because the status code is required
+	/// for request metrics.
+	pub fn compile(format: &str) -> Result<AccessLogFormat> {
+		if format.trim().is_empty() {
+			bail!("Log format is empty");
+		}
+		
+		let mut elements = Vec::new();
+		let mut literal = String::new();
+		let mut chars = format.chars().peekable();
+		
+		while let Some(c) = chars.next() {
+			match c {
+				'\\' => match chars.next() {
+					Some('n') => literal.push('\n'),
+					Some('t') => literal.push('\t'),
+					Some(escaped) => literal.push(escaped),
+					None => literal.push('\\'),
+				},
+				
+				'%' => {
+					if chars.peek() == Some(&'%') {
+						chars.next();
+						literal.push('%');
+						continue;
+					}
+					
+					while chars.next_if(|c| matches!(c, '<' | '>' | '!' | ',' | '0'..='9')).is_some() {
+						// Modifiers do not affect the layout of the log line.
+					}
+					
+					let argument = if chars.next_if_eq(&'{').is_some() {
+						let mut argument = String::new();
+						loop {
+							match chars.next() {
+								Some('}') => break,
+								Some(c) => argument.push(c),
+								None => bail!("Log format has an unclosed '{{'"),
+							}
+						}
+						Some(argument)
+					} else {
+						None
+					};
+					
+					let directive = chars.next().ok_or_else(|| anyhow!("Log format ends with an incomplete directive"))?;
+					let field = if directive == '^' {
+						// Trailer directives `%{X}^ti` and `%{X}^to` are the only ones with more than one character.
+						let name = chars.next().into_iter().chain(chars.next()).collect::<String>();
+						match name.as_str() {
+							"ti" | "to" => AccessLogField::Other(format!("^{}", name)),
+							_ => bail!("Log format has an invalid directive: %^{}", name),
+						}
+					} else {
+						parse_field(directive, argument)?
+					};
+					
+					if !literal.is_empty() {
+						elements.push(AccessLogFormatElement::Literal(std::mem::take(&mut literal)));
+					}
+					
+					if matches!(elements.last(), Some(AccessLogFormatElement::Field(previous)) if *previous != AccessLogField::Time) {
+						bail!("Log format has fields that are not separated by any text");
+					}
+					
+					elements.push(AccessLogFormatElement::Field(field));
+				}
+				
+				_ => literal.push(c),
+			}
+		}
+		
+		if !literal.is_empty() {
+			elements.push(AccessLogFormatElement::Literal(literal));
+		}
+		
+		if !elements.iter().any(|element| matches!(element, AccessLogFormatElement::Field(AccessLogField::Status))) {
+			bail!("Log format must contain %s or %>s");
+		}
+		
+		Ok(AccessLogFormat { elements })
+	}
+}
+
+fn parse_field(directive: char, argument: Option<String>) -> Result<AccessLogField> {
+	Ok(match (directive, argument) {
+		('h', _) => AccessLogField::RemoteHost,
+		('l', _) => AccessLogField::RemoteLogname,
+		('u', _) => AccessLogField::RemoteUser,
+		('t', None) => AccessLogField::Time,
+		('t', Some(_)) => AccessLogField::CustomTime,
+		('r', _) => AccessLogField::Request,
+		('s', _) => AccessLogField::Status,
+		('b', _) => AccessLogField::ResponseBytesClf,
+		('O', _) => AccessLogField::BytesSent,
+		('I', _) => AccessLogField::BytesReceived,
+		('D', _) => AccessLogField::ResponseTime(TimeUnit::Microseconds),
+		('T', None) => AccessLogField::ResponseTime(TimeUnit::Seconds),
+		('T', Some(unit)) => AccessLogField::ResponseTime(match unit.as_str() {
+			"s" => TimeUnit::Seconds,
+			"ms" => TimeUnit::Milliseconds,
+			"us" => TimeUnit::Microseconds,
+			_ => bail!("Log format has an invalid time unit: {}", unit),
+		}),
+		('i', Some(header)) => AccessLogField::RequestHeader(header),
+		('o', Some(header)) => AccessLogField::ResponseHeader(header),
+		('i' | 'o', None) => bail!("Log format has a %{}, which requires a header name", directive),
+		('v', _) => AccessLogField::ServerName,
+		('V', _) => AccessLogField::RequestServerName,
+		('p', _) => AccessLogField::ServerPort,
+		('k', _) => AccessLogField::KeepAliveRequests,
+		('X', _) => AccessLogField::ConnectionStatus,
+		('L', _) => AccessLogField::LogId,
+		(c, _) if c.is_ascii_alphabetic() => AccessLogField::Other(c.to_string()),
+		(c, _) => bail!("Log format has an invalid directive: %{}", c),
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{ACCESS_LOG_FORMAT_PRESETS, AccessLogField, AccessLogFormat, AccessLogFormatElement, DEFAULT_ACCESS_LOG_FORMAT, TimeUnit};
+	
+	fn literal(str: &str) -> AccessLogFormatElement {
+		AccessLogFormatElement::Literal(str.to_string())
+	}
+	
+	fn field(field: AccessLogField) -> AccessLogFormatElement {
+		AccessLogFormatElement::Field(field)
+	}
+	
+	#[test]
+	fn empty_format() {
+		assert!(matches!(AccessLogFormat::compile(" "), Err(err) if err.to_string() == "Log format is empty"));
+	}
+	
+	#[test]
+	fn default_format() {
+		let format = AccessLogFormat::compile(DEFAULT_ACCESS_LOG_FORMAT).unwrap();
+		assert_eq!(format.elements, vec![
+			field(AccessLogField::Time),
+			literal(" "),
+			field(AccessLogField::RemoteHost),
+			literal(" \""),
+			field(AccessLogField::Request),
+			literal("\" "),
+			field(AccessLogField::Status),
+			literal(" "),
+			field(AccessLogField::BytesSent),
+			literal(" "),
+			field(AccessLogField::ResponseTime(TimeUnit::Milliseconds)),
+			literal(" \""),
+			field(AccessLogField::RequestHeader("Referer".to_string())),
+			literal("\" \""),
+			field(AccessLogField::RequestHeader("User-Agent".to_string())),
+			literal("\""),
+		]);
+	}
+	
+	#[test]
+	fn presets() {
+		for (name, format) in ACCESS_LOG_FORMAT_PRESETS {
+			assert_eq!(AccessLogFormat::from_preset_or_format(name).unwrap().elements, AccessLogFormat::compile(format).unwrap().elements);
+		}
+	}
+	
+	#[test]
+	fn vhost_combined_preset() {
+		let format = AccessLogFormat::from_preset_or_format("vhost_combined").unwrap();
+		assert_eq!(format.elements[0..4], [
+			field(AccessLogField::ServerName),
+			literal(":"),
+			field(AccessLogField::ServerPort),
+			literal(" "),
+		]);
+	}
+	
+	#[test]
+	fn custom_format_is_not_a_preset() {
+		let format = AccessLogFormat::from_preset_or_format("%>s %h").unwrap();
+		assert_eq!(format.elements, vec![
+			field(AccessLogField::Status),
+			literal(" "),
+			field(AccessLogField::RemoteHost),
+		]);
+	}
+	
+	#[test]
+	fn escaped_quotes_and_percent() {
+		let format = AccessLogFormat::compile("%>s \\\"%r\\\" 100%%").unwrap();
+		assert_eq!(format.elements, vec![
+			field(AccessLogField::Status),
+			literal(" \""),
+			field(AccessLogField::Request),
+			literal("\" 100%"),
+		]);
+	}
+	
+	#[test]
+	fn modifiers_are_ignored() {
+		let format = AccessLogFormat::compile("%<s %!200,304{Referer}i").unwrap();
+		assert_eq!(format.elements, vec![
+			field(AccessLogField::Status),
+			literal(" "),
+			field(AccessLogField::RequestHeader("Referer".to_string())),
+		]);
+	}
+	
+	#[test]
+	fn trailer_directives() {
+		let format = AccessLogFormat::compile("%>s %{X-Trailer}^ti %{X-Trailer}^to").unwrap();
+		assert_eq!(format.elements, vec![
+			field(AccessLogField::Status),
+			literal(" "),
+			field(AccessLogField::Other("^ti".to_string())),
+			literal(" "),
+			field(AccessLogField::Other("^to".to_string())),
+		]);
+		
+		assert!(matches!(AccessLogFormat::compile("%>s %{X-Trailer}^tx"), Err(err) if err.to_string() == "Log format has an invalid directive: %^tx"));
+	}
+	
+	#[test]
+	fn missing_status() {
+		assert!(matches!(AccessLogFormat::compile("%h %r"), Err(err) if err.to_string() == "Log format must contain %s or %>s"));
+	}
+	
+	#[test]
+	fn adjacent_fields() {
+		assert!(matches!(AccessLogFormat::compile("%h%>s"), Err(err) if err.to_string() == "Log format has fields that are not separated by any text"));
+		assert!(AccessLogFormat::compile("%t%>s").is_ok());
+	}
+	
+	#[test]
+	fn invalid_directives() {
+		assert!(matches!(AccessLogFormat::compile("%>s %{Referer"), Err(err) if err.to_string() == "Log format has an unclosed '{'"));
+		assert!(matches!(AccessLogFormat::compile("%>s %"), Err(err) if err.to_string() == "Log format ends with an incomplete directive"));
+		assert!(matches!(AccessLogFormat::compile("%>s %i"), Err(err) if err.to_string() == "Log format has a %i, which requires a header name"));
+		assert!(matches!(AccessLogFormat::compile("%>s %{min}T"), Err(err) if err.to_string() == "Log format has an invalid time unit: min"));
+		assert!(matches!(AccessLogFormat::compile("%>s %-"), Err(err) if err.to_string() == "Log format has an invalid directive: %-"));
+	}
+}
@@ -1,58 +1,336 @@
 use std::fmt::{Display, Error, Formatter};
 
+use crate::logs::access_log_format::{AccessLogField, AccessLogFormat, AccessLogFormatElement};
+
 pub struct AccessLogLineParts<'a> {
-	pub time: &'a str,
-	pub remote_host: &'a str,
-	pub request: &'a str,
+	pub time: Option<&'a str>,
+	pub remote_host: Option<&'a str>,
+	pub request: Option<&'a str>,
 	pub response_status: &'a str,
-	pub response_bytes: &'a str,
-	pub response_time_ms: &'a str,
-	pub referer: &'a str,
-	pub user_agent: &'a str,
+	pub response_bytes: Option<u64>,
+	pub response_time_seconds: Option<f64>,
+	pub referer: Option<&'a str>,
+	pub user_agent: Option<&'a str>,
+}
+
+struct OptionalPart<T>(Option<T>);
+
+impl<T: Display> Display for OptionalPart<T> {
+	fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+		match &self.0 {
+			Some(value) => write!(f, "{}", value),
+			None => write!(f, "-"),
+		}
+	}
 }
 
 impl Display for AccessLogLineParts<'_> {
 	fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
-		write!(f, "[{}] {} \"{}\" {} {} {} \"{}\" \"{}\"", self.time, self.remote_host, self.request, self.response_status, self.response_bytes, self.response_time_ms, self.referer, self.user_agent)
+		write!(f, "[{}] {} \"{}\" {} {} {} \"{}\" \"{}\"", OptionalPart(self.time), OptionalPart(self.remote_host), OptionalPart(self.request), self.response_status, OptionalPart(self.response_bytes), OptionalPart(self.response_time_seconds), OptionalPart(self.referer), OptionalPart(self.user_agent))
 	}
 }
 
 impl<'a> AccessLogLineParts<'a> {
-	pub fn parse(line: &'a str) -> Result<AccessLogLineParts<'a>, ParseError> {
-		let (time, line) = extract_between_chars(line, '[', ']').ok_or(ParseError::TimeBracketsNotFound)?;
-		let (remote_host, line) = next_space_delimited_part(line).ok_or(ParseError::RemoteHostNotFound)?;
-		let (request, line) = extract_between_chars(line.trim_start_matches(' '), '"', '"').ok_or(ParseError::RequestNotFound)?;
-		let (response_status, line) = next_space_delimited_part(line).ok_or(ParseError::ResponseStatusNotFound)?;
-		let (response_bytes, line) = next_space_delimited_part(line).ok_or(ParseError::ResponseBytesNotFound)?;
-		let (response_time_ms, line) = next_space_delimited_part(line).ok_or(ParseError::ResponseTimeNotFound)?;
-		let (referer, line) = extract_between_chars(line.trim_start_matches(' '), '"', '"').ok_or(ParseError::RefererNotFound)?;
-		let (user_agent, _) = extract_between_chars(line.trim_start_matches(' '), '"', '"').ok_or(ParseError::UserAgentNotFound)?;
-		Ok(AccessLogLineParts { time, remote_host, request, response_status, response_bytes, response_time_ms, referer, user_agent })
+	pub fn parse(line: &'a str, format: &AccessLogFormat) -> Result<AccessLogLineParts<'a>, ParseError> {
+		let mut parts = AccessLogLineParts {
+			time: None,
+			remote_host: None,
+			request: None,
+			response_status: "",
+			response_bytes: None,
+			response_time_seconds: None,
+			referer: None,
+			user_agent: None,
+		};
+		
+		let mut line = line;
+		let mut elements = format.elements.iter().peekable();
+		
+		while let Some(element) = elements.next() {
+			match element {
+				AccessLogFormatElement::Literal(literal) => {
+					line = line.strip_prefix(literal.as_str()).ok_or(ParseError::LiteralNotFound)?;
+				}
+				
+				AccessLogFormatElement::Field(AccessLogField::Time) => {
+					let (time, rest) = extract_between_chars(line, '[', ']').ok_or(ParseError::TimeBracketsNotFound)?;
+					parts.time = Some(time);
+					line = rest;
+				}
+				
+				AccessLogFormatElement::Field(field) => {
+					let (value, rest) = match elements.peek() {
+						Some(AccessLogFormatElement::Literal(literal)) => split_at_unescaped(line, literal),
+						_ if line.is_empty() => None,
+						_ => Some((line, "")),
+					}.ok_or_else(|| ParseError::not_found(field))?;
+					
+					parts.assign(field, value)?;
+					line = rest;
+				}
+			}
+		}
+		
+		Ok(parts)
+	}
+	
+	fn assign(&mut self, field: &AccessLogField, value: &'a str) -> Result<(), ParseError> {
+		match field {
+			AccessLogField::RemoteHost => {
+				self.remote_host.get_or_insert(value);
+			}
+			
+			AccessLogField::Request => {
+				self.request.get_or_insert(value);
+			}
+			
+			AccessLogField::Status => {
+				if !is_valid_response_status(value) {
+					return Err(ParseError::ResponseStatusInvalid);
+				}
+				
+				self.response_status = value;
+			}
+			
+			AccessLogField::BytesSent | AccessLogField::ResponseBytesClf => {
+				// Apache logs `-` instead of `0` when no bytes were sent.
+				let bytes = if value == "-" { 0 } else { value.parse::<u64>().map_err(|_| ParseError::ResponseBytesInvalid)? };
+				
+				// %O includes headers, so it is preferred over %b if both are present.
+				if *field == AccessLogField::BytesSent || self.response_bytes.is_none() {
+					self.response_bytes = Some(bytes);
+				}
+			}
+			
+			AccessLogField::ResponseTime(unit) => {
+				let time = value.parse::<u64>().map_err(|_| ParseError::ResponseTimeInvalid)?;
+				self.response_time_seconds.get_or_insert(unit.to_seconds(time));
+			}
+			
+			AccessLogField::RequestHeader(header) if header.eq_ignore_ascii_case("Referer") => {
+				self.referer.get_or_insert(value);
+			}
+			
+			AccessLogField::RequestHeader(header) if header.eq_ignore_ascii_case("User-Agent") => {
+				self.user_agent.get_or_insert(value);
+			}
+			
+			_ => {}
+		}
+		
+		Ok(())
+	}
+	
+	pub fn response_status_class(&self) -> &'static str {
+		match self.response_status.as_bytes().first() {
+			Some(b'1') => "1xx",
+			Some(b'2') => "2xx",
+			Some(b'3') => "3xx",
+			Some(b'4') => "4xx",
+			Some(b'5') => "5xx",
+			_ => "unknown",
+		}
 	}
 }
 
-fn next_space_delimited_part(str: &str) -> Option<(&str, &str)> {
-	return str.trim_start_matches(' ').split_once(' ')
+fn is_valid_response_status(str: &str) -> bool {
+	str.len() == 3 && str.bytes().all(|b| b.is_ascii_digit()) && matches!(str.as_bytes()[0], b'1'..=b'5')
+}
+
+/// Splits the string before the first occurrence of the separator that is not escaped with a backslash.
+/// Apache escapes quotes and backslashes in request lines and headers, so quoted fields can be split on quotes.
+fn split_at_unescaped<'a>(str: &'a str, separator: &str) -> Option<(&'a str, &'a str)> {
+	let mut escaped = false;
+	
+	for (index, c) in str.char_indices() {
+		if escaped {
+			escaped = false;
+		} else if c == '\\' {
+			escaped = true;
+		} else if str[index..].starts_with(separator) {
+			return Some(str.split_at(index));
+		}
+	}
+	
+	None
 }
 
 fn extract_between_chars(str: &str, left_side: char, right_side: char) -> Option<(&str, &str)> {
-	let str = str.trim_start_matches(' ');
-	let next_char = str.chars().next()?;
-	return if next_char == left_side {
+	if str.starts_with(left_side) {
 		str.get(1..)?.split_once(right_side)
 	} else {
 		None
-	};
+	}
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub enum ParseError {
+	LiteralNotFound,
+	FieldNotFound,
 	TimeBracketsNotFound,
 	RemoteHostNotFound,
 	RequestNotFound,
 	ResponseStatusNotFound,
+	ResponseStatusInvalid,
 	ResponseBytesNotFound,
+	ResponseBytesInvalid,
 	ResponseTimeNotFound,
+	ResponseTimeInvalid,
 	RefererNotFound,
 	UserAgentNotFound,
 }
+
+impl ParseError {
+	fn not_found(field: &AccessLogField) -> ParseError {
+		match field {
+			AccessLogField::RemoteHost => ParseError::RemoteHostNotFound,
+			AccessLogField::Request => ParseError::RequestNotFound,
+			AccessLogField::Status => ParseError::ResponseStatusNotFound,
+			AccessLogField::BytesSent | AccessLogField::ResponseBytesClf => ParseError::ResponseBytesNotFound,
+			AccessLogField::ResponseTime(_) => ParseError::ResponseTimeNotFound,
+			AccessLogField::RequestHeader(header) if header.eq_ignore_ascii_case("Referer") => ParseError::RefererNotFound,
+			AccessLogField::RequestHeader(header) if header.eq_ignore_ascii_case("User-Agent") => ParseError::UserAgentNotFound,
+			_ => ParseError::FieldNotFound,
+		}
+	}
+	
+	pub fn reason(self) -> &'static str {
+		match self {
+			ParseError::LiteralNotFound => "literal_not_found",
+			ParseError::FieldNotFound => "field_not_found",
+			ParseError::TimeBracketsNotFound => "time_brackets_not_found",
+			ParseError::RemoteHostNotFound => "remote_host_not_found",
+			ParseError::RequestNotFound => "request_not_found",
+			ParseError::ResponseStatusNotFound => "response_status_not_found",
+			ParseError::ResponseStatusInvalid => "response_status_invalid",
+			ParseError::ResponseBytesNotFound => "response_bytes_not_found",
+			ParseError::ResponseBytesInvalid => "response_bytes_invalid",
+			ParseError::ResponseTimeNotFound => "response_time_not_found",
+			ParseError::ResponseTimeInvalid => "response_time_invalid",
+			ParseError::RefererNotFound => "referer_not_found",
+			ParseError::UserAgentNotFound => "user_agent_not_found",
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::logs::access_log_format::{AccessLogFormat, DEFAULT_ACCESS_LOG_FORMAT};
+	
+	use super::{AccessLogLineParts, ParseError};
+	
+	const VALID_LINE: &str = "[10/Oct/2023:13:55:36 +0000] 127.0.0.1 \"GET /index.html HTTP/1.1\" 404 512 12 \"-\" \"curl/8.0\"";
+	
+	fn parse(line: &str) -> Result<AccessLogLineParts<'_>, ParseError> {
+		AccessLogLineParts::parse(line, &AccessLogFormat::compile(DEFAULT_ACCESS_LOG_FORMAT).unwrap())
+	}
+	
+	#[test]
+	fn valid_line() {
+		let parts = parse(VALID_LINE).unwrap();
+		assert_eq!(parts.time, Some("10/Oct/2023:13:55:36 +0000"));
+		assert_eq!(parts.remote_host, Some("127.0.0.1"));
+		assert_eq!(parts.request, Some("GET /index.html HTTP/1.1"));
+		assert_eq!(parts.response_status, "404");
+		assert_eq!(parts.response_bytes, Some(512));
+		assert_eq!(parts.response_time_seconds, Some(0.012));
+		assert_eq!(parts.referer, Some("-"));
+		assert_eq!(parts.user_agent, Some("curl/8.0"));
+	}
+	
+	#[test]
+	fn escaped_quotes() {
+		let line = "[10/Oct/2023:13:55:36 +0000] 127.0.0.1 \"GET /\\\" HTTP/1.1\" 200 512 12 \"-\" \"say \\\"hi\\\"\"";
+		let parts = parse(line).unwrap();
+		assert_eq!(parts.request, Some("GET /\\\" HTTP/1.1"));
+		assert_eq!(parts.user_agent, Some("say \\\"hi\\\""));
+	}
+	
+	#[test]
+	fn response_status_class() {
+		assert_eq!(parse(VALID_LINE).unwrap().response_status_class(), "4xx");
+	}
+	
+	#[test]
+	fn response_bytes_dash() {
+		let line = "[10/Oct/2023:13:55:36 +0000] 127.0.0.1 \"GET / HTTP/1.1\" 304 - 12 \"-\" \"curl/8.0\"";
+		assert_eq!(parse(line).unwrap().response_bytes, Some(0));
+	}
+	
+	#[test]
+	fn invalid_response_bytes() {
+		let line = "[10/Oct/2023:13:55:36 +0000] 127.0.0.1 \"GET / HTTP/1.1\" 200 -1 12 \"-\" \"curl/8.0\"";
+		assert_eq!(parse(line).err(), Some(ParseError::ResponseBytesInvalid));
+	}
+	
+	#[test]
+	fn invalid_response_time() {
+		let line = "[10/Oct/2023:13:55:36 +0000] 127.0.0.1 \"GET / HTTP/1.1\" 200 512 abc \"-\" \"curl/8.0\"";
+		assert_eq!(parse(line).err(), Some(ParseError::ResponseTimeInvalid));
+	}
+	
+	#[test]
+	fn invalid_response_status() {
+		let line = "[10/Oct/2023:13:55:36 +0000] 127.0.0.1 \"GET / HTTP/1.1\" 2000 512 12 \"-\" \"curl/8.0\"";
+		assert_eq!(parse(line).err(), Some(ParseError::ResponseStatusInvalid));
+	}
+	
+	#[test]
+	fn unexpected_text() {
+		let line = "[10/Oct/2023:13:55:36 +0000]-127.0.0.1 \"GET / HTTP/1.1\" 200 512 12 \"-\" \"curl/8.0\"";
+		assert_eq!(parse(line).err(), Some(ParseError::LiteralNotFound));
+	}
+	
+	#[test]
+	fn missing_time_brackets() {
+		let line = "10/Oct/2023:13:55:36 +0000 127.0.0.1 \"GET / HTTP/1.1\" 200 512 12 \"-\" \"curl/8.0\"";
+		assert_eq!(parse(line).err(), Some(ParseError::TimeBracketsNotFound));
+	}
+	
+	#[test]
+	fn missing_user_agent() {
+		let line = "[10/Oct/2023:13:55:36 +0000] 127.0.0.1 \"GET / HTTP/1.1\" 200 512 12 \"-\"";
+		assert_eq!(parse(line).err(), Some(ParseError::RefererNotFound));
+		
+		let line = "[10/Oct/2023:13:55:36 +0000] 127.0.0.1 \"GET / HTTP/1.1\" 200 512 12 \"-\" \"curl/8.0";
+		assert_eq!(parse(line).err(), Some(ParseError::UserAgentNotFound));
+	}
+	
+	#[test]
+	fn custom_format() {
+		let format = AccessLogFormat::compile("%h %l %u %t \"%r\" %>s %b %D").unwrap();
+		let line = "127.0.0.1 - frank [10/Oct/2000:13:55:36 -0700] \"GET /apache_pb.gif HTTP/1.0\" 200 2326 1500";
+		let parts = AccessLogLineParts::parse(line, &format).unwrap();
+		assert_eq!(parts.remote_host, Some("127.0.0.1"));
+		assert_eq!(parts.time, Some("10/Oct/2000:13:55:36 -0700"));
+		assert_eq!(parts.request, Some("GET /apache_pb.gif HTTP/1.0"));
+		assert_eq!(parts.response_status, "200");
+		assert_eq!(parts.response_bytes, Some(2326));
+		assert_eq!(parts.response_time_seconds, Some(0.0015));
+		assert_eq!(parts.referer, None);
+		assert_eq!(parts.user_agent, None);
+	}
+	
+	#[test]
+	fn combined_preset() {
+		let format = AccessLogFormat::from_preset_or_format("combined").unwrap();
+		let line = "192.168.0.5 - - [10/Oct/2023:13:55:36 +0000] \"POST /login HTTP/2.0\" 302 1024 \"https://example.com/\" \"Mozilla/5.0 (X11; Linux x86_64)\"";
+		let parts = AccessLogLineParts::parse(line, &format).unwrap();
+		assert_eq!(parts.remote_host, Some("192.168.0.5"));
+		assert_eq!(parts.request, Some("POST /login HTTP/2.0"));
+		assert_eq!(parts.response_status, "302");
+		assert_eq!(parts.response_bytes, Some(1024));
+		assert_eq!(parts.response_time_seconds, None);
+		assert_eq!(parts.referer, Some("https://example.com/"));
+		assert_eq!(parts.user_agent, Some("Mozilla/5.0 (X11; Linux x86_64)"));
+	}
+	
+	#[test]
+	fn bytes_sent_preferred_over_clf_bytes() {
+		let format = AccessLogFormat::compile("%>s %O %b").unwrap();
+		assert_eq!(AccessLogLineParts::parse("200 300 -", &format).unwrap().response_bytes, Some(300));
+		
+		let format = AccessLogFormat::compile("%>s %b %O").unwrap();
+		assert_eq!(AccessLogLineParts::parse("200 - 300", &format).unwrap().response_bytes, Some(300));
+	}
+}
@@ -0,0 +1,174 @@
+use std::fmt::{Display, Error, Formatter};
+
+const LOG_LEVELS: [&str; 16] = ["emerg", "alert", "crit", "error", "warn", "notice", "info", "debug", "trace1", "trace2", "trace3", "trace4", "trace5", "trace6", "trace7", "trace8"];
+
+/// Parts of an error log line in the default Apache 2.4 `ErrorLogFormat`:
+///
+/// `[time] [module:level] [pid N:tid N] [client ip:port] AHxxxxx: message`
+///
+/// The process/thread and client parts are optional, as is the message code.
+pub struct ErrorLogLineParts<'a> {
+	pub time: &'a str,
+	pub module: &'a str,
+	pub level: &'a str,
+	pub pid: Option<&'a str>,
+	pub tid: Option<&'a str>,
+	pub client: Option<&'a str>,
+	pub code: Option<&'a str>,
+	pub message: &'a str,
+}
+
+impl Display for ErrorLogLineParts<'_> {
+	fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+		write!(f, "[{}] [{}:{}]", self.time, self.module, self.level)?;
+		
+		match (self.pid, self.tid) {
+			(Some(pid), Some(tid)) => write!(f, " [pid {}:tid {}]", pid, tid)?,
+			(Some(pid), None) => write!(f, " [pid {}]", pid)?,
+			_ => {}
+		}
+		
+		if let Some(client) = self.client {
+			write!(f, " [client {}]", client)?;
+		}
+		
+		if let Some(code) = self.code {
+			write!(f, " {}:", code)?;
+		}
+		
+		write!(f, " {}", self.message)
+	}
+}
+
+impl<'a> ErrorLogLineParts<'a> {
+	pub fn parse(line: &'a str) -> Result<ErrorLogLineParts<'a>, ParseError> {
+		let (time, line) = extract_between_chars(line, '[', ']').ok_or(ParseError::TimeBracketsNotFound)?;
+		let (module_and_level, mut line) = extract_between_chars(line, '[', ']').ok_or(ParseError::ModuleAndLevelNotFound)?;
+		let (module, level) = module_and_level.rsplit_once(':').ok_or(ParseError::ModuleAndLevelNotFound)?;
+		
+		if !LOG_LEVELS.contains(&level) {
+			return Err(ParseError::LevelInvalid);
+		}
+		
+		let mut pid = None;
+		let mut tid = None;
+		let mut client = None;
+		
+		while let Some((part, rest)) = extract_between_chars(line, '[', ']') {
+			if let Some(process) = part.strip_prefix("pid ") {
+				if let Some((process_id, thread)) = process.split_once(':') {
+					pid = Some(process_id);
+					tid = Some(thread.strip_prefix("tid ").unwrap_or(thread));
+				} else {
+					pid = Some(process);
+				}
+			} else if let Some(address) = part.strip_prefix("client ") {
+				client = Some(address);
+			} else if !part.starts_with("remote ") {
+				break;
+			}
+			
+			line = rest;
+		}
+		
+		let message = line.trim_start_matches(' ');
+		let (code, message) = match message.split_once(": ") {
+			Some((code, message)) if is_message_code(code) => (Some(code), message),
+			_ => (None, message),
+		};
+		
+		Ok(ErrorLogLineParts { time, module, level, pid, tid, client, code, message })
+	}
+}
+
+/// Checks whether the string is an Apache message code, such as `AH00124`.
+pub fn is_message_code(str: &str) -> bool {
+	matches!(str.strip_prefix("AH"), Some(number) if number.len() == 5 && number.bytes().all(|b| b.is_ascii_digit()))
+}
+
+fn extract_between_chars(str: &str, left_side: char, right_side: char) -> Option<(&str, &str)> {
+	let str = str.trim_start_matches(' ');
+	if str.starts_with(left_side) {
+		str.get(1..)?.split_once(right_side)
+	} else {
+		None
+	}
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ParseError {
+	TimeBracketsNotFound,
+	ModuleAndLevelNotFound,
+	LevelInvalid,
+}
+
+impl ParseError {
+	pub fn reason(self) -> &'static str {
+		match self {
+			ParseError::TimeBracketsNotFound => "time_brackets_not_found",
+			ParseError::ModuleAndLevelNotFound => "module_and_level_not_found",
+			ParseError::LevelInvalid => "level_invalid",
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{ErrorLogLineParts, ParseError};
+	
+	#[test]
+	fn full_line() {
+		let line = "[Wed Oct 11 14:32:52.123456 2023] [core:error] [pid 1234:tid 140245] [client 10.0.0.1:51234] AH00124: Request exceeded the limit of 10 internal redirects.";
+		let parts = ErrorLogLineParts::parse(line).unwrap();
+		assert_eq!(parts.time, "Wed Oct 11 14:32:52.123456 2023");
+		assert_eq!(parts.module, "core");
+		assert_eq!(parts.level, "error");
+		assert_eq!(parts.pid, Some("1234"));
+		assert_eq!(parts.tid, Some("140245"));
+		assert_eq!(parts.client, Some("10.0.0.1:51234"));
+		assert_eq!(parts.code, Some("AH00124"));
+		assert_eq!(parts.message, "Request exceeded the limit of 10 internal redirects.");
+	}
+	
+	#[test]
+	fn minimal_line() {
+		let line = "[Wed Oct 11 14:32:52.123456 2023] [mpm_prefork:notice] [pid 42] Apache/2.4.57 configured -- resuming normal operations";
+		let parts = ErrorLogLineParts::parse(line).unwrap();
+		assert_eq!(parts.module, "mpm_prefork");
+		assert_eq!(parts.level, "notice");
+		assert_eq!(parts.pid, Some("42"));
+		assert_eq!(parts.tid, None);
+		assert_eq!(parts.client, None);
+		assert_eq!(parts.code, None);
+		assert_eq!(parts.message, "Apache/2.4.57 configured -- resuming normal operations");
+	}
+	
+	#[test]
+	fn empty_module() {
+		let parts = ErrorLogLineParts::parse("[Wed Oct 11 14:32:52 2023] [:crit] message").unwrap();
+		assert_eq!(parts.module, "");
+		assert_eq!(parts.level, "crit");
+		assert_eq!(parts.message, "message");
+	}
+	
+	#[test]
+	fn message_with_brackets() {
+		let parts = ErrorLogLineParts::parse("[Wed Oct 11 14:32:52 2023] [php:warn] [pid 1] [client 10.0.0.1:1] [abc] def").unwrap();
+		assert_eq!(parts.client, Some("10.0.0.1:1"));
+		assert_eq!(parts.message, "[abc] def");
+	}
+	
+	#[test]
+	fn display() {
+		let line = "[Wed Oct 11 14:32:52 2023] [core:error] [pid 1234:tid 140245] [client 10.0.0.1:51234] AH00124: Request exceeded the limit";
+		assert_eq!(ErrorLogLineParts::parse(line).unwrap().to_string(), line);
+	}
+	
+	#[test]
+	fn invalid_lines() {
+		assert_eq!(ErrorLogLineParts::parse("AH00558: apache2: Could not reliably determine the server's fully qualified domain name").err(), Some(ParseError::TimeBracketsNotFound));
+		assert_eq!(ErrorLogLineParts::parse("[Wed Oct 11 14:32:52 2023] core error").err(), Some(ParseError::ModuleAndLevelNotFound));
+		assert_eq!(ErrorLogLineParts::parse("[Wed Oct 11 14:32:52 2023] [core] message").err(), Some(ParseError::ModuleAndLevelNotFound));
+		assert_eq!(ErrorLogLineParts::parse("[Wed Oct 11 14:32:52 2023] [core:fatal] message").err(), Some(ParseError::LevelInvalid));
+	}
+}
@@ -1,24 +1,98 @@
+use std::cmp::max;
 use std::collections::HashMap;
+use std::{fs, mem};
 use std::path::{Path, PathBuf};
+use std::sync::{Arc, PoisonError, Weak};
+use std::time::{Duration, SystemTime};
 
-use notify::{ErrorKind, Event, recommended_watcher, RecommendedWatcher, RecursiveMode, Result, Watcher};
-use tokio::sync::mpsc::Sender;
-use tokio::sync::Mutex;
+use notify::{ErrorKind, Event, EventKind, recommended_watcher, RecommendedWatcher, RecursiveMode, Result, Watcher};
+use notify::event::{CreateKind, DataChange, ModifyKind};
+use tokio::sync::{Mutex, Notify};
+
+use crate::logs::read_positions::FileIdentity;
+
+/// How filesystem changes are detected.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum WatcherBackend {
+	/// Uses native filesystem events, and falls back to polling for paths that cannot be watched natively.
+	Auto,
+	/// Uses native filesystem events (e.g. inotify on Linux).
+	Native,
+	/// Periodically checks the size and identity of watched files, which also works on filesystems that do not
+	/// report native events, such as NFS.
+	Polling,
+}
 
 pub struct FsWatcher {
-	watcher: Mutex<RecommendedWatcher>,
+	backend: WatcherBackend,
+	native_watcher: Option<Mutex<RecommendedWatcher>>,
+	polling_watcher: Option<PollingWatcher>,
 }
 
 impl FsWatcher {
-	pub fn new(callbacks: FsEventCallbacks) -> Result<Self> {
+	pub fn new(callbacks: FsEventCallbacks, backend: WatcherBackend, poll_interval: Duration) -> Result<Self> {
+		let native_watcher = match backend {
+			WatcherBackend::Native => Some(Self::create_native_watcher(callbacks.clone())?),
+			WatcherBackend::Polling => None,
+			WatcherBackend::Auto => match Self::create_native_watcher(callbacks.clone()) {
+				Ok(watcher) => Some(watcher),
+				Err(e) => {
+					println!("[FsWatcher] Could not create native filesystem watcher, falling back to polling: {}", e);
+					None
+				}
+			},
+		};
+		
+		let polling_watcher = match backend {
+			WatcherBackend::Native => None,
+			WatcherBackend::Polling | WatcherBackend::Auto => Some(PollingWatcher::start(callbacks, poll_interval)),
+		};
+		
+		Ok(Self { backend, native_watcher, polling_watcher })
+	}
+	
+	fn create_native_watcher(callbacks: FsEventCallbacks) -> Result<Mutex<RecommendedWatcher>> {
 		let watcher = recommended_watcher(move |event| callbacks.handle_event(event))?;
-		let watcher = Mutex::new(watcher);
+		Ok(Mutex::new(watcher))
+	}
+	
+	/// Watches the folder of log files for files being created. Only native watchers need this, because polling
+	/// detects a replaced file by watching the file itself.
+	pub async fn watch_folder(&self, path: &Path) -> Result<()> {
+		let Some(native_watcher) = &self.native_watcher else {
+			return Ok(());
+		};
 		
-		Ok(Self { watcher })
+		match Self::watch_natively(native_watcher, path).await {
+			Ok(()) => Ok(()),
+			Err(e) if self.backend == WatcherBackend::Auto => {
+				println!("[FsWatcher] Could not watch \"{}\" natively, falling back to polling its files: {}", path.to_string_lossy(), e);
+				Ok(())
+			}
+			Err(e) => Err(e),
+		}
 	}
 	
 	pub async fn watch(&self, path: &Path) -> Result<()> {
-		let mut watcher = self.watcher.lock().await;
+		if let Some(native_watcher) = &self.native_watcher {
+			match Self::watch_natively(native_watcher, path).await {
+				Ok(()) => return Ok(()),
+				Err(e) if self.backend == WatcherBackend::Auto => {
+					println!("[FsWatcher] Could not watch \"{}\" natively, falling back to polling: {}", path.to_string_lossy(), e);
+				}
+				Err(e) => return Err(e),
+			}
+		}
+		
+		if let Some(polling_watcher) = &self.polling_watcher {
+			polling_watcher.watch(path).await;
+		}
+		
+		Ok(())
+	}
+	
+	async fn watch_natively(native_watcher: &Mutex<RecommendedWatcher>, path: &Path) -> Result<()> {
+		let mut watcher = native_watcher.lock().await;
 		
 		if let Err(e) = watcher.unwatch(path) {
 			if !matches!(e.kind, ErrorKind::WatchNotFound) {
@@ -28,29 +102,209 @@ impl FsWatcher {
 		
 		watcher.watch(path, RecursiveMode::NonRecursive)
 	}
+	
+	pub async fn unwatch(&self, path: &Path) -> Result<()> {
+		if let Some(polling_watcher) = &self.polling_watcher {
+			polling_watcher.unwatch(path);
+		}
+		
+		if let Some(native_watcher) = &self.native_watcher {
+			let mut watcher = native_watcher.lock().await;
+			
+			if let Err(e) = watcher.unwatch(path) {
+				if !matches!(e.kind, ErrorKind::WatchNotFound) {
+					return Err(e);
+				}
+			}
+		}
+		
+		Ok(())
+	}
+}
+
+/// Detects changes by periodically reading the metadata of watched paths. Changes are reported as the same events
+/// that native watchers report for log files, i.e. a file creation when the file at a path is replaced, and a data
+/// modification when its size or modification time changes.
+struct PollingWatcher {
+	paths: Arc<std::sync::Mutex<HashMap<PathBuf, Option<PolledFileState>>>>,
+}
+
+impl PollingWatcher {
+	fn start(callbacks: FsEventCallbacks, poll_interval: Duration) -> Self {
+		let paths = Arc::new(std::sync::Mutex::new(HashMap::new()));
+		tokio::spawn(Self::poll_periodically(Arc::downgrade(&paths), callbacks, poll_interval));
+		Self { paths }
+	}
+	
+	async fn watch(&self, path: &Path) {
+		let state = PolledFileState::read_in_background(vec![path.to_path_buf()]).await.pop().and_then(|(_, state)| state);
+		self.paths.lock().unwrap_or_else(PoisonError::into_inner).insert(path.to_path_buf(), state);
+	}
+	
+	fn unwatch(&self, path: &Path) {
+		self.paths.lock().unwrap_or_else(PoisonError::into_inner).remove(path);
+	}
+	
+	async fn poll_periodically(paths: Weak<std::sync::Mutex<HashMap<PathBuf, Option<PolledFileState>>>>, callbacks: FsEventCallbacks, poll_interval: Duration) {
+		let mut interval = tokio::time::interval(poll_interval);
+		
+		loop {
+			interval.tick().await;
+			
+			let Some(paths) = paths.upgrade() else {
+				break;
+			};
+			
+			// Reading metadata blocks, so the paths are copied out of the lock and read on another thread.
+			let polled_paths = paths.lock().unwrap_or_else(PoisonError::into_inner).keys().cloned().collect::<Vec<_>>();
+			let new_states = PolledFileState::read_in_background(polled_paths).await;
+			
+			let mut events = Vec::new();
+			let mut paths = paths.lock().unwrap_or_else(PoisonError::into_inner);
+			
+			for (path, new_state) in new_states {
+				// The path may have been unwatched while its metadata was read.
+				let Some(state) = paths.get_mut(&path) else {
+					continue;
+				};
+				
+				if let Some(kind) = PolledFileState::compare(state.as_ref(), new_state.as_ref()) {
+					events.push(Event::new(kind).add_path(path));
+				}
+				
+				*state = new_state;
+			}
+			
+			drop(paths);
+			
+			for event in events {
+				callbacks.handle_event(Ok(event));
+			}
+		}
+	}
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+struct PolledFileState {
+	identity: Option<FileIdentity>,
+	size: u64,
+	modified: Option<SystemTime>,
 }
 
+impl PolledFileState {
+	fn read(path: &Path) -> Option<PolledFileState> {
+		let metadata = fs::metadata(path).ok()?;
+		Some(PolledFileState { identity: FileIdentity::of(&metadata), size: metadata.len(), modified: metadata.modified().ok() })
+	}
+	
+	/// Reads the states of the paths on a thread where blocking is allowed. Returns no states if the thread fails.
+	async fn read_in_background(paths: Vec<PathBuf>) -> Vec<(PathBuf, Option<PolledFileState>)> {
+		tokio::task::spawn_blocking(move || paths.into_iter().map(|path| {
+			let state = PolledFileState::read(&path);
+			(path, state)
+		}).collect()).await.unwrap_or_default()
+	}
+	
+	fn compare(old: Option<&PolledFileState>, new: Option<&PolledFileState>) -> Option<EventKind> {
+		match (old, new) {
+			(None, Some(_)) => Some(EventKind::Create(CreateKind::File)),
+			(Some(old), Some(new)) if old.identity != new.identity => Some(EventKind::Create(CreateKind::File)),
+			(Some(old), Some(new)) if old != new => Some(EventKind::Modify(ModifyKind::Data(DataChange::Any))),
+			_ => None,
+		}
+	}
+}
+
+/// The most significant filesystem event that has not been handled yet.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd)]
+pub enum CoalescedFsEvent {
+	None = 0,
+	NewData = 1,
+	NewFile = 2,
+}
+
+impl CoalescedFsEvent {
+	fn merge(self, event: &Event) -> CoalescedFsEvent {
+		match event.kind {
+			EventKind::Modify(ModifyKind::Data(_)) => {
+				max(self, CoalescedFsEvent::NewData)
+			}
+			
+			EventKind::Create(CreateKind::File) => {
+				max(self, CoalescedFsEvent::NewFile)
+			}
+			
+			_ => self
+		}
+	}
+}
+
+/// Filesystem events of a registered path that have not been handled yet. Events are merged as they arrive
+/// instead of being queued, so that no event is lost however quickly they arrive.
+struct PendingFsEvents {
+	event: std::sync::Mutex<CoalescedFsEvent>,
+	notify: Notify,
+}
+
+impl PendingFsEvents {
+	fn add(&self, event: &Event) {
+		let mut pending_event = self.event.lock().unwrap_or_else(PoisonError::into_inner);
+		let merged_event = pending_event.merge(event);
+		
+		if merged_event != CoalescedFsEvent::None {
+			*pending_event = merged_event;
+			self.notify.notify_one();
+		}
+	}
+}
+
+pub struct FsEventReceiver {
+	pending: Arc<PendingFsEvents>,
+}
+
+impl FsEventReceiver {
+	/// Waits until events arrive, and returns all events that arrived since the last call merged together.
+	pub async fn recv(&self) -> CoalescedFsEvent {
+		loop {
+			self.pending.notify.notified().await;
+			
+			let event = mem::replace(&mut *self.pending.event.lock().unwrap_or_else(PoisonError::into_inner), CoalescedFsEvent::None);
+			if event != CoalescedFsEvent::None {
+				return event;
+			}
+		}
+	}
+}
+
+/// Routes filesystem events to the receivers of registered paths. Clones share the same registrations,
+/// so paths can be registered and unregistered after the [FsWatcher] is created.
+#[derive(Clone)]
 pub struct FsEventCallbacks {
-	senders: HashMap<PathBuf, Sender<Event>>,
+	receivers: Arc<std::sync::Mutex<HashMap<PathBuf, Arc<PendingFsEvents>>>>,
 }
 
 impl FsEventCallbacks {
 	pub fn new() -> Self {
-		Self { senders: HashMap::new() }
+		Self { receivers: Arc::new(std::sync::Mutex::new(HashMap::new())) }
+	}
+	
+	pub fn register(&self, path: &Path) -> FsEventReceiver {
+		let pending = Arc::new(PendingFsEvents { event: std::sync::Mutex::new(CoalescedFsEvent::None), notify: Notify::new() });
+		self.receivers.lock().unwrap_or_else(PoisonError::into_inner).insert(path.to_path_buf(), Arc::clone(&pending));
+		FsEventReceiver { pending }
 	}
 	
-	pub fn register(&mut self, path: &Path, sender: Sender<Event>) {
-		self.senders.insert(path.to_path_buf(), sender);
+	pub fn unregister(&self, path: &Path) {
+		self.receivers.lock().unwrap_or_else(PoisonError::into_inner).remove(path);
 	}
 	
 	fn handle_event(&self, event: Result<Event>) {
 		match event {
 			Ok(event) => {
+				let receivers = self.receivers.lock().unwrap_or_else(PoisonError::into_inner);
 				for path in &event.paths {
-					if let Some(sender) = self.senders.get(path) {
-						if let Err(e) = sender.try_send(event.clone()) {
-							println!("[FsWatcher] Error sending filesystem event for path \"{}\": {}", path.to_string_lossy(), e);
-						}
+					if let Some(pending) = receivers.get(path) {
+						pending.add(&event);
 					}
 				}
 			}
@@ -60,3 +314,51 @@ impl FsEventCallbacks {
 		}
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use std::path::Path;
+	use std::time::{Duration, SystemTime};
+	
+	use notify::{Event, EventKind};
+	use notify::event::{CreateKind, DataChange, ModifyKind};
+	
+	use crate::logs::read_positions::FileIdentity;
+	
+	use super::{CoalescedFsEvent, FsEventCallbacks, PolledFileState};
+	
+	fn state(inode: u64, size: u64) -> PolledFileState {
+		PolledFileState { identity: Some(FileIdentity { device: 1, inode }), size, modified: Some(SystemTime::UNIX_EPOCH + Duration::from_secs(size)) }
+	}
+	
+	#[test]
+	fn polled_changes() {
+		assert_eq!(PolledFileState::compare(Some(&state(1, 10)), Some(&state(1, 10))), None);
+		assert_eq!(PolledFileState::compare(Some(&state(1, 10)), Some(&state(1, 20))), Some(EventKind::Modify(ModifyKind::Data(DataChange::Any))));
+		assert_eq!(PolledFileState::compare(Some(&state(1, 10)), Some(&state(1, 0))), Some(EventKind::Modify(ModifyKind::Data(DataChange::Any))));
+		assert_eq!(PolledFileState::compare(Some(&state(1, 10)), Some(&state(2, 10))), Some(EventKind::Create(CreateKind::File)));
+		assert_eq!(PolledFileState::compare(None, Some(&state(1, 10))), Some(EventKind::Create(CreateKind::File)));
+		assert_eq!(PolledFileState::compare(Some(&state(1, 10)), None), None);
+	}
+	
+	#[tokio::test]
+	async fn events_are_coalesced() {
+		let callbacks = FsEventCallbacks::new();
+		let receiver = callbacks.register(Path::new("/var/log/apache2/access.log"));
+		
+		let event = |kind: EventKind, path: &str| Ok(Event::new(kind).add_path(path.into()));
+		
+		for _ in 0..100 {
+			callbacks.handle_event(event(EventKind::Modify(ModifyKind::Data(DataChange::Any)), "/var/log/apache2/access.log"));
+		}
+		
+		callbacks.handle_event(event(EventKind::Create(CreateKind::File), "/var/log/apache2/access.log"));
+		callbacks.handle_event(event(EventKind::Modify(ModifyKind::Data(DataChange::Any)), "/var/log/apache2/access.log"));
+		callbacks.handle_event(event(EventKind::Create(CreateKind::File), "/var/log/apache2/error.log"));
+		
+		assert_eq!(receiver.recv().await, CoalescedFsEvent::NewFile);
+		
+		callbacks.handle_event(event(EventKind::Modify(ModifyKind::Data(DataChange::Any)), "/var/log/apache2/access.log"));
+		assert_eq!(receiver.recv().await, CoalescedFsEvent::NewData);
+	}
+}
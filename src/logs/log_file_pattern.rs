@@ -1,111 +1,504 @@
-use std::fs::DirEntry;
 use std::io;
 use std::io::ErrorKind;
 use std::path::{Path, PathBuf};
 
-use anyhow::{anyhow, bail, Result};
+use anyhow::{anyhow, bail, Context, Result};
 use path_slash::PathExt;
+use regex::Regex;
+
+use crate::metrics::RESERVED_LABEL_NAMES;
+
+/// Prefix of patterns that are regular expressions.
+pub const REGEX_PATTERN_PREFIX: &str = "regex:";
+
+/// Characters that make a folder name in a regex pattern not literal.
+const REGEX_SPECIAL_CHARACTERS: [char; 14] = ['\\', '.', '+', '*', '?', '(', ')', '|', '[', ']', '{', '}', '^', '$'];
+
+/// Characters that make a folder name in a wildcard pattern not literal.
+const WILDCARD_SPECIAL_CHARACTERS: [char; 6] = ['\\', '*', '?', '[', '(', ')'];
 
 /// Reads and parses an environment variable that determines the path and file name pattern of log files.
 ///
 /// Supports 3 pattern types:
 ///
 /// 1. A simple path to a file.
-/// 2. A path with a wildcard anywhere in the file name.
-/// 3. A path with a standalone wildcard component (i.e. no prefix or suffix in the folder name).
+/// 2. A path with wildcards (`*`, `?`, `[abc]`, and `**` for any number of folders). The parts of the path
+///    in parentheses around wildcards become the `file` label, or if there are none, the parts matched by wildcards.
+/// 3. A regular expression prefixed with `regex:`, which must match the whole path. Named capture groups become labels.
 pub fn parse_log_file_pattern_from_str(pattern: &str) -> Result<LogFilePattern> {
+	if let Some(regex) = pattern.strip_prefix(REGEX_PATTERN_PREFIX) {
+		return parse_log_file_pattern_regex(regex);
+	}
+	
 	let pattern = Path::new(pattern).to_slash().ok_or_else(|| anyhow!("Path is invalid"))?;
 	if pattern.trim().is_empty() {
 		bail!("Path is empty");
 	}
 	
-	if let Some((left, right)) = pattern.split_once('*') {
-		parse_log_file_pattern_split_on_wildcard(left, right)
+	if pattern.contains(['*', '?', '[']) {
+		parse_log_file_pattern_wildcards(&pattern)
 	} else {
 		Ok(LogFilePattern::WithoutWildcard(pattern.to_string()))
 	}
 }
 
-fn parse_log_file_pattern_split_on_wildcard(left: &str, right: &str) -> Result<LogFilePattern> {
-	if left.contains('*') || right.contains('*') {
-		bail!("Path has too many wildcards");
+fn parse_log_file_pattern_wildcards(pattern: &str) -> Result<LogFilePattern> {
+	let (regex, label_groups) = translate_wildcards_to_regex(pattern)?;
+	let regex = Regex::new(&regex).with_context(|| format!("Invalid wildcard pattern: {}", pattern))?;
+	
+	let (base_folder, remaining_folders) = find_base_folder(pattern, WILDCARD_SPECIAL_CHARACTERS);
+	let max_depth = if pattern.contains("**") { None } else { Some(remaining_folders) };
+	
+	// Every folder below the base folder is matched by one part of the pattern, up to a recursive wildcard.
+	let mut folder_filters = Vec::new();
+	let mut remaining_parts = remaining_pattern(pattern, &base_folder).split('/').collect::<Vec<_>>();
+	remaining_parts.pop();
+	
+	for part in remaining_parts.into_iter().take_while(|part| *part != "**") {
+		folder_filters.push(FolderFilter::from_literal_prefix(part, WILDCARD_SPECIAL_CHARACTERS));
 	}
 	
-	if left.ends_with('/') && right.starts_with('/') {
-		return Ok(LogFilePattern::WithFolderNameWildcard(PatternWithFolderNameWildcard {
-			path_prefix: left.to_string(),
-			path_suffix: right[1..].to_string(),
-		}));
+	Ok(LogFilePattern::WithWildcards(PatternWithRegex { base_folder, regex, label_groups, max_depth, folder_filters }))
+}
+
+/// Translates a wildcard pattern into a regular expression, and returns the indices of the capture groups
+/// that form the `file` label.
+fn translate_wildcards_to_regex(pattern: &str) -> Result<(String, Vec<usize>)> {
+	let chars = pattern.chars().collect::<Vec<_>>();
+	let group_parentheses = find_label_group_parentheses(&chars)?;
+	let has_explicit_groups = !group_parentheses.is_empty();
+	
+	let mut regex = String::from("^");
+	let mut group_count = 0;
+	let mut in_group = false;
+	
+	let mut add_wildcard = |regex: &mut String, wildcard_regex: &str, in_group: bool| {
+		if has_explicit_groups || in_group {
+			regex.push_str(wildcard_regex);
+		} else {
+			group_count += 1;
+			regex.push('(');
+			regex.push_str(wildcard_regex);
+			regex.push(')');
+		}
+	};
+	
+	let mut explicit_group_count = 0;
+	let mut i = 0;
+	
+	while i < chars.len() {
+		match chars[i] {
+			'\\' => {
+				i += 1;
+				let escaped = chars.get(i).ok_or_else(|| anyhow!("Pattern ends with an escape character"))?;
+				regex.push_str(&regex::escape(&escaped.to_string()));
+			}
+			'(' if group_parentheses.contains(&i) => {
+				in_group = true;
+				explicit_group_count += 1;
+				regex.push('(');
+			}
+			')' if group_parentheses.contains(&i) => {
+				in_group = false;
+				regex.push(')');
+			}
+			'*' if chars.get(i + 1) == Some(&'*') => {
+				let starts_folder = i == 0 || chars[i - 1] == '/';
+				let ends_folder = chars.get(i + 2).is_none_or(|c| *c == '/');
+				if !starts_folder || !ends_folder {
+					bail!("Recursive wildcard must be a whole folder name");
+				}
+				
+				if chars.get(i + 2).is_some() {
+					add_wildcard(&mut regex, "(?:[^/]+/)*", in_group);
+					i += 2;
+				} else {
+					add_wildcard(&mut regex, "(?:[^/]+/)*[^/]+", in_group);
+					i += 1;
+				}
+			}
+			'*' => add_wildcard(&mut regex, "[^/]*", in_group),
+			'?' => add_wildcard(&mut regex, "[^/]", in_group),
+			'[' => {
+				let length = chars[i..].iter().skip(2).position(|c| *c == ']').ok_or_else(|| anyhow!("Pattern has an unclosed character class"))? + 3;
+				add_wildcard(&mut regex, &translate_character_class(&chars[i + 1..i + length - 1]), in_group);
+				i += length - 1;
+			}
+			c => regex.push_str(&regex::escape(&c.to_string())),
+		}
+		
+		i += 1;
 	}
 	
-	if right.contains('/') {
-		bail!("Path has a folder wildcard with a prefix or suffix");
+	regex.push('$');
+	
+	// Capture groups are numbered from 1, and either all groups are explicit or all are wildcards.
+	let label_groups = (1..=group_count.max(explicit_group_count)).collect();
+	Ok((regex, label_groups))
+}
+
+/// Translates the contents of a character class such as `[a-z]` or `[!0-9]`. A closing bracket
+/// as the first character is part of the class.
+fn translate_character_class(contents: &[char]) -> String {
+	let mut regex = String::from("[");
+	let mut contents = contents;
+	
+	if let Some(('!' | '^', rest)) = contents.split_first() {
+		regex.push_str("^/");
+		contents = rest;
+	}
+	
+	for c in contents {
+		if matches!(c, '\\' | '[' | ']' | '&' | '~' | '^') {
+			regex.push('\\');
+		}
+		regex.push(*c);
+	}
+	
+	regex.push(']');
+	regex
+}
+
+/// Returns the positions of parentheses that form label groups. Only parentheses around a wildcard form a group,
+/// so that folder names such as `site (old)` keep their literal meaning.
+fn find_label_group_parentheses(chars: &[char]) -> Result<Vec<usize>> {
+	let mut group_parentheses = Vec::new();
+	let mut open_group: Option<(usize, bool)> = None;
+	let mut i = 0;
+	
+	while i < chars.len() {
+		match chars[i] {
+			'\\' => i += 1,
+			'(' => {
+				if open_group.is_some() {
+					bail!("Pattern has nested parentheses");
+				}
+				open_group = Some((i, false));
+			}
+			')' => {
+				let (start, has_wildcard) = open_group.take().ok_or_else(|| anyhow!("Pattern has unmatched parentheses"))?;
+				if has_wildcard {
+					group_parentheses.extend([start, i]);
+				}
+			}
+			'*' | '?' | '[' => {
+				if let Some((_, has_wildcard)) = &mut open_group {
+					*has_wildcard = true;
+				}
+				
+				// Parentheses inside a character class are literal.
+				if chars[i] == '[' {
+					if let Some(length) = chars[i..].iter().skip(2).position(|c| *c == ']') {
+						i += length + 2;
+					}
+				}
+			}
+			_ => {}
+		}
+		
+		i += 1;
+	}
+	
+	if open_group.is_some() {
+		bail!("Pattern has unmatched parentheses");
+	}
+	
+	Ok(group_parentheses)
+}
+
+fn parse_log_file_pattern_regex(pattern: &str) -> Result<LogFilePattern> {
+	if pattern.trim().is_empty() {
+		bail!("Path is empty");
 	}
 	
-	if let Some((folder_path, file_name_prefix)) = left.rsplit_once('/') {
-		Ok(LogFilePattern::WithFileNameWildcard(PatternWithFileNameWildcard {
-			path: folder_path.to_string(),
-			file_name_prefix: file_name_prefix.to_string(),
-			file_name_suffix: right.to_string(),
-		}))
+	let regex = Regex::new(&format!("^(?:{})$", pattern)).with_context(|| format!("Invalid regular expression: {}", pattern))?;
+	
+	for name in regex.capture_names().flatten() {
+		if !is_valid_label_name(name) {
+			bail!("Capture group name is not a valid label name: {}", name);
+		}
+		if RESERVED_LABEL_NAMES.contains(&name) || name.starts_with("__") {
+			bail!("Capture group name is reserved: {}", name);
+		}
+	}
+	
+	let label_groups = regex.capture_names().position(|name| name == Some("file")).into_iter().collect();
+	let pattern = pattern.strip_prefix('^').unwrap_or(pattern);
+	
+	// Groups and inline flags at the start can change what the literal folders after them match, and every
+	// alternative at the top level has its own literal folders, so only the folders common to all are searched.
+	let alternatives = split_top_level_alternatives(pattern);
+	if alternatives.len() > 1 || pattern.starts_with('(') {
+		let base_folder = common_base_folder(alternatives.into_iter().map(alternative_base_folder));
+		return Ok(LogFilePattern::WithRegex(PatternWithRegex { base_folder, regex, label_groups, max_depth: None, folder_filters: Vec::new() }));
+	}
+	
+	let (base_folder, _) = find_base_folder(pattern, REGEX_SPECIAL_CHARACTERS);
+	
+	// Only the literal start of the pattern after the base folder is known to be matched by a single folder,
+	// because the rest of a regular expression may match any number of folders.
+	let remaining_pattern = remaining_pattern(pattern, &base_folder);
+	let mut literal_length = remaining_pattern.find(REGEX_SPECIAL_CHARACTERS).unwrap_or(remaining_pattern.len());
+	
+	if remaining_pattern[literal_length..].starts_with(['?', '*', '{']) {
+		// The character before a quantifier may be missing.
+		literal_length = remaining_pattern[..literal_length].char_indices().next_back().map_or(0, |(index, _)| index);
+	}
+	
+	let folder_filters = vec![FolderFilter { prefix: remaining_pattern[..literal_length].to_string(), exact: false }];
+	
+	Ok(LogFilePattern::WithRegex(PatternWithRegex { base_folder, regex, label_groups, max_depth: None, folder_filters }))
+}
+
+/// Checks that a capture group name is a valid Prometheus label name.
+fn is_valid_label_name(name: &str) -> bool {
+	name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Splits a regular expression at alternations that are not inside a group or a character class.
+fn split_top_level_alternatives(pattern: &str) -> Vec<&str> {
+	let mut alternatives = Vec::new();
+	let mut start = 0;
+	let mut group_depth = 0;
+	let mut in_class = false;
+	let mut chars = pattern.char_indices().peekable();
+	
+	while let Some((index, c)) = chars.next() {
+		match c {
+			'\\' => {
+				chars.next();
+			}
+			'[' if !in_class => {
+				in_class = true;
+				
+				// A closing bracket at the start of a character class is literal.
+				chars.next_if(|(_, c)| *c == '^');
+				chars.next_if(|(_, c)| *c == ']');
+			}
+			']' => in_class = false,
+			'(' if !in_class => group_depth += 1,
+			')' if !in_class => group_depth -= 1,
+			'|' if !in_class && group_depth == 0 => {
+				alternatives.push(&pattern[start..index]);
+				start = index + 1;
+			}
+			_ => {}
+		}
+	}
+	
+	alternatives.push(&pattern[start..]);
+	alternatives
+}
+
+/// Returns the base folder of one alternative of a regular expression. If it starts with a group or inline flags,
+/// the search starts at the root folder, or at the current folder if the path is relative.
+fn alternative_base_folder(alternative: &str) -> String {
+	let alternative = alternative.strip_prefix('^').unwrap_or(alternative);
+	if !alternative.starts_with('(') {
+		return find_base_folder(alternative, REGEX_SPECIAL_CHARACTERS).0;
+	}
+	
+	String::from(if skip_group_openings(alternative).starts_with('/') { "/" } else { "" })
+}
+
+/// Skips anchors, inline flags and the openings of groups at the start of a regular expression.
+fn skip_group_openings(mut pattern: &str) -> &str {
+	loop {
+		pattern = pattern.trim_start_matches('^');
+		
+		if let Some(rest) = pattern.strip_prefix("(?") {
+			// Inline flags such as `(?i)`, or the opening of a group such as `(?:` or `(?P<name>`.
+			let Some(end) = rest.find([')', ':', '>']) else {
+				return pattern;
+			};
+			pattern = &rest[end + 1..];
+		} else if let Some(rest) = pattern.strip_prefix('(') {
+			pattern = rest;
+		} else {
+			return pattern;
+		}
+	}
+}
+
+/// Returns the longest folder path that all base folders start with.
+fn common_base_folder(base_folders: impl Iterator<Item = String>) -> String {
+	let mut common_folders: Option<Vec<String>> = None;
+	
+	for base_folder in base_folders {
+		let folders = if base_folder.is_empty() { Vec::new() } else { base_folder.trim_end_matches('/').split('/').map(String::from).collect() };
+		common_folders = Some(match common_folders {
+			Some(common_folders) => common_folders.into_iter().zip(folders).take_while(|(a, b)| a == b).map(|(a, _)| a).collect(),
+			None => folders,
+		});
+	}
+	
+	let common_folders = common_folders.unwrap_or_default();
+	if common_folders == [""] {
+		String::from("/")
 	} else {
-		Ok(LogFilePattern::WithFileNameWildcard(PatternWithFileNameWildcard {
-			path: String::new(),
-			file_name_prefix: left.to_string(),
-			file_name_suffix: right.to_string(),
-		}))
+		common_folders.join("/")
 	}
 }
 
-#[derive(Debug)]
-pub struct PatternWithFileNameWildcard {
-	path: String,
-	file_name_prefix: String,
-	file_name_suffix: String,
+/// Returns the longest folder path at the start of the pattern that does not contain any special characters,
+/// so that the search does not need to start at the root folder, and the number of folders after it.
+fn find_base_folder<const N: usize>(pattern: &str, special_characters: [char; N]) -> (String, usize) {
+	let mut folders = pattern.split('/').collect::<Vec<_>>();
+	folders.pop();
+	
+	let folder_count = folders.len();
+	let literal_folders = folders.into_iter().take_while(|folder| !folder.contains(special_characters)).collect::<Vec<_>>();
+	let remaining_folders = folder_count - literal_folders.len();
+	
+	let base_folder = if literal_folders == [""] {
+		String::from("/")
+	} else {
+		literal_folders.join("/")
+	};
+	
+	(base_folder, remaining_folders)
+}
+
+/// Returns the part of the pattern after the base folder.
+fn remaining_pattern<'a>(pattern: &'a str, base_folder: &str) -> &'a str {
+	pattern[base_folder.len()..].trim_start_matches('/')
+}
+
+/// Restricts the names of folders at one level below the base folder, so that folders which cannot contain
+/// matching files are not searched.
+#[derive(Debug, Eq, PartialEq)]
+struct FolderFilter {
+	prefix: String,
+	/// Whether the folder name must be equal to the prefix.
+	exact: bool,
 }
 
-impl PatternWithFileNameWildcard {
-	fn match_wildcard<'a>(&self, file_name: &'a str) -> Option<&'a str> {
-		return file_name.strip_prefix(&self.file_name_prefix).and_then(|r| r.strip_suffix(&self.file_name_suffix));
+impl FolderFilter {
+	fn from_literal_prefix<const N: usize>(part: &str, special_characters: [char; N]) -> FolderFilter {
+		match part.find(special_characters) {
+			Some(index) => FolderFilter { prefix: part[..index].to_string(), exact: false },
+			None => FolderFilter { prefix: part.to_string(), exact: true },
+		}
 	}
 	
-	fn match_wildcard_on_dir_entry(&self, dir_entry: &DirEntry) -> Option<String> {
-		dir_entry.file_name()
-			.to_str()
-			.and_then(|file_name| self.match_wildcard(file_name))
-			.map(|wildcard_match| wildcard_match.to_string())
+	fn matches(&self, folder_name: &str) -> bool {
+		if self.exact {
+			folder_name == self.prefix
+		} else {
+			folder_name.starts_with(&self.prefix)
+		}
 	}
 }
 
 #[derive(Debug)]
-pub struct PatternWithFolderNameWildcard {
-	path_prefix: String,
-	path_suffix: String,
+pub struct PatternWithRegex {
+	base_folder: String,
+	regex: Regex,
+	/// Capture groups whose matches are joined with `/` to form the `file` label.
+	label_groups: Vec<usize>,
+	/// How many folders below the base folder can contain matching files, or `None` if there is no limit.
+	max_depth: Option<usize>,
+	/// Filters of folder names at each level below the base folder. Levels without a filter are not restricted.
+	folder_filters: Vec<FolderFilter>,
 }
 
-impl PatternWithFolderNameWildcard {
-	fn match_wildcard_on_dir_entry(dir_entry: &DirEntry) -> Option<String> {
-		return if matches!(dir_entry.file_type(), Ok(entry_type) if entry_type.is_dir()) {
-			dir_entry.file_name().to_str().map(|s| s.into())
-		} else {
-			None
+impl PatternWithRegex {
+	/// Matches the path against the regular expression. Named capture groups other than `file` become additional labels.
+	fn match_path(&self, path: &str) -> Option<LogFilePath> {
+		let captures = self.regex.captures(path)?;
+		
+		let label = self.label_groups.iter()
+			.filter_map(|group| captures.get(*group))
+			.map(|value| value.as_str().trim_matches('/'))
+			.filter(|value| !value.is_empty())
+			.collect::<Vec<_>>()
+			.join("/");
+		
+		let pattern_labels = self.regex.capture_names()
+			.flatten()
+			.filter(|name| *name != "file")
+			.map(|name| (name.to_string(), captures.name(name).map_or("", |value| value.as_str()).to_string()))
+			.collect();
+		
+		Some(LogFilePath { path: PathBuf::from(path), label, pattern_labels })
+	}
+	
+	/// Searches a folder and its subfolders for matching files. Subfolders and entries that cannot be read, or that
+	/// are deleted during the search, are skipped so that they do not prevent finding the other files.
+	fn search_folder(&self, folder: &str, depth: usize, result: &mut Vec<LogFilePath>) -> Result<(), io::Error> {
+		let folder_path = if folder.is_empty() { Path::new(".") } else { Path::new(folder) };
+		
+		let dir_entries = match folder_path.read_dir() {
+			Ok(dir_entries) => dir_entries,
+			Err(e) if depth > 0 && can_skip(&e) => {
+				println!("[LogWatcher] Skipping folder \"{}\": {}", folder_path.display(), e);
+				return Ok(());
+			}
+			Err(e) => return Err(e),
 		};
+		
+		for dir_entry in dir_entries {
+			let dir_entry = match dir_entry {
+				Ok(dir_entry) => dir_entry,
+				Err(e) if can_skip(&e) => {
+					println!("[LogWatcher] Skipping entry of folder \"{}\": {}", folder_path.display(), e);
+					continue;
+				}
+				Err(e) => return Err(e),
+			};
+			
+			let Some(file_name) = dir_entry.file_name().to_str().map(String::from) else {
+				continue;
+			};
+			
+			let path = if folder.is_empty() {
+				file_name.clone()
+			} else if folder.ends_with('/') {
+				format!("{}{}", folder, file_name)
+			} else {
+				format!("{}/{}", folder, file_name)
+			};
+			
+			let file_type = match dir_entry.file_type() {
+				Ok(file_type) => file_type,
+				Err(e) if can_skip(&e) => {
+					println!("[LogWatcher] Skipping \"{}\": {}", path, e);
+					continue;
+				}
+				Err(e) => return Err(e),
+			};
+			
+			if file_type.is_dir() {
+				let can_contain_matches = self.folder_filters.get(depth).is_none_or(|filter| filter.matches(&file_name));
+				if can_contain_matches && self.max_depth.is_none_or(|max_depth| depth < max_depth) {
+					self.search_folder(&path, depth + 1, result)?;
+				}
+			} else if dir_entry.path().is_file() {
+				result.extend(self.match_path(&path));
+			}
+		}
+		
+		Ok(())
 	}
 }
 
+/// Whether an error while searching for log files only affects a single folder or file.
+fn can_skip(error: &io::Error) -> bool {
+	matches!(error.kind(), ErrorKind::PermissionDenied | ErrorKind::NotFound)
+}
+
 #[derive(Debug)]
 pub enum LogFilePattern {
 	WithoutWildcard(String),
-	WithFileNameWildcard(PatternWithFileNameWildcard),
-	WithFolderNameWildcard(PatternWithFolderNameWildcard),
+	WithWildcards(PatternWithRegex),
+	WithRegex(PatternWithRegex),
 }
 
 impl LogFilePattern {
 	pub fn search(&self) -> Result<Vec<LogFilePath>, io::Error> { // TODO error message
 		match self {
 			Self::WithoutWildcard(path) => Self::search_without_wildcard(path),
-			Self::WithFileNameWildcard(pattern) => Self::search_with_file_name_wildcard(pattern),
-			Self::WithFolderNameWildcard(pattern) => Self::search_with_folder_name_wildcard(pattern)
+			Self::WithWildcards(pattern) | Self::WithRegex(pattern) => Self::search_with_regex(pattern),
 		}
 	}
 	
@@ -117,32 +510,9 @@ impl LogFilePattern {
 		}
 	}
 	
-	fn search_with_file_name_wildcard(pattern: &PatternWithFileNameWildcard) -> Result<Vec<LogFilePath>, io::Error> {
+	fn search_with_regex(pattern: &PatternWithRegex) -> Result<Vec<LogFilePath>, io::Error> {
 		let mut result = Vec::new();
-		
-		for dir_entry in Path::new(&pattern.path).read_dir()? {
-			let dir_entry = dir_entry?;
-			if let Some(wildcard_match) = pattern.match_wildcard_on_dir_entry(&dir_entry) {
-				result.push(LogFilePath { path: dir_entry.path(), label: wildcard_match });
-			}
-		}
-		
-		Ok(result)
-	}
-	
-	fn search_with_folder_name_wildcard(pattern: &PatternWithFolderNameWildcard) -> Result<Vec<LogFilePath>, io::Error> {
-		let mut result = Vec::new();
-		
-		for dir_entry in Path::new(&pattern.path_prefix).read_dir()? {
-			let dir_entry = dir_entry?;
-			if let Some(wildcard_match) = PatternWithFolderNameWildcard::match_wildcard_on_dir_entry(&dir_entry) {
-				let full_path = dir_entry.path().join(&pattern.path_suffix);
-				if full_path.is_file() {
-					result.push(LogFilePath { path: full_path, label: wildcard_match })
-				}
-			}
-		}
-		
+		pattern.search_folder(&pattern.base_folder, 0, &mut result)?;
 		Ok(result)
 	}
 }
@@ -150,6 +520,8 @@ impl LogFilePattern {
 pub struct LogFilePath {
 	pub path: PathBuf,
 	pub label: String,
+	/// Labels from named capture groups of a regex pattern, in the order of the groups.
+	pub pattern_labels: Vec<(String, String)>,
 }
 
 impl LogFilePath {
@@ -157,57 +529,275 @@ impl LogFilePath {
 		LogFilePath {
 			path: PathBuf::from(s),
 			label: String::default(),
+			pattern_labels: Vec::new(),
 		}
 	}
 }
 
 #[cfg(test)]
 mod tests {
-	use super::{LogFilePattern, parse_log_file_pattern_from_str};
+	use std::fs;
+	use std::path::PathBuf;
 	
-	#[test]
-	fn empty_path() {
-		assert!(matches!(parse_log_file_pattern_from_str(""), Err(err) if err.to_string() == "Path is empty"));
-		assert!(matches!(parse_log_file_pattern_from_str("  "), Err(err) if err.to_string() == "Path is empty"));
+	use crate::logs::create_test_folder;
+	
+	use super::{FolderFilter, LogFilePattern, parse_log_file_pattern_from_str, PatternWithRegex};
+	
+	fn parse_wildcards(pattern: &str) -> PatternWithRegex {
+		match parse_log_file_pattern_from_str(pattern) {
+			Ok(LogFilePattern::WithWildcards(pattern)) => pattern,
+			Ok(pattern) => panic!("Pattern is not a wildcard pattern: {:?}", pattern),
+			Err(err) => panic!("Pattern is invalid: {}", err),
+		}
 	}
 	
-	#[test]
-	fn too_many_wildcards() {
-		assert!(matches!(parse_log_file_pattern_from_str("/path/*/to/files/*.log"), Err(err) if err.to_string() == "Path has too many wildcards"));
+	fn label(pattern: &PatternWithRegex, path: &str) -> Option<String> {
+		pattern.match_path(path).map(|log_file| log_file.label)
 	}
 	
-	#[test]
-	fn folder_wildcard_with_prefix_not_supported() {
-		assert!(matches!(parse_log_file_pattern_from_str("/path/*abc/to/files/access.log"), Err(err) if err.to_string() == "Path has a folder wildcard with a prefix or suffix"));
+	fn search(pattern: &str) -> Vec<PathBuf> {
+		let mut paths = parse_log_file_pattern_from_str(pattern).unwrap().search().unwrap().into_iter().map(|log_file| log_file.path).collect::<Vec<_>>();
+		paths.sort();
+		paths
 	}
 	
 	#[test]
-	fn folder_wildcard_with_suffix_not_supported() {
-		assert!(matches!(parse_log_file_pattern_from_str("/path/abc*/to/files/access.log"), Err(err) if err.to_string() == "Path has a folder wildcard with a prefix or suffix"));
+	fn empty_path() {
+		assert!(matches!(parse_log_file_pattern_from_str(""), Err(err) if err.to_string() == "Path is empty"));
+		assert!(matches!(parse_log_file_pattern_from_str("  "), Err(err) if err.to_string() == "Path is empty"));
 	}
 	
 	#[test]
 	fn valid_without_wildcard() {
 		assert!(matches!(parse_log_file_pattern_from_str("/path/to/file/access.log"), Ok(LogFilePattern::WithoutWildcard(path)) if path == "/path/to/file/access.log"));
+		assert!(matches!(parse_log_file_pattern_from_str("/path/to/site (old)/access.log"), Ok(LogFilePattern::WithoutWildcard(path)) if path == "/path/to/site (old)/access.log"));
 	}
 	
 	#[test]
 	fn valid_with_file_name_wildcard_prefix() {
-		assert!(matches!(parse_log_file_pattern_from_str("/path/to/files/access_*"), Ok(LogFilePattern::WithFileNameWildcard(pattern)) if pattern.path == "/path/to/files" && pattern.file_name_prefix == "access_" && pattern.file_name_suffix.is_empty()));
+		let pattern = parse_wildcards("/path/to/files/access_*");
+		assert_eq!(pattern.base_folder, "/path/to/files");
+		assert_eq!(pattern.max_depth, Some(0));
+		assert_eq!(label(&pattern, "/path/to/files/access_first"), Some(String::from("first")));
+		assert_eq!(label(&pattern, "/path/to/files/error_first"), None);
 	}
 	
 	#[test]
 	fn valid_with_file_name_wildcard_suffix() {
-		assert!(matches!(parse_log_file_pattern_from_str("/path/to/files/*_access.log"), Ok(LogFilePattern::WithFileNameWildcard(pattern)) if pattern.path == "/path/to/files" && pattern.file_name_prefix.is_empty() && pattern.file_name_suffix == "_access.log"));
+		let pattern = parse_wildcards("/path/to/files/*_access.log");
+		assert_eq!(label(&pattern, "/path/to/files/first_access.log"), Some(String::from("first")));
+		assert_eq!(label(&pattern, "/path/to/files/first_access.log.1"), None);
 	}
 	
 	#[test]
 	fn valid_with_file_name_wildcard_both() {
-		assert!(matches!(parse_log_file_pattern_from_str("/path/to/files/access_*.log"), Ok(LogFilePattern::WithFileNameWildcard(pattern)) if pattern.path == "/path/to/files" && pattern.file_name_prefix == "access_" && pattern.file_name_suffix == ".log"));
+		let pattern = parse_wildcards("/path/to/files/access_*.log");
+		assert_eq!(label(&pattern, "/path/to/files/access_first.log"), Some(String::from("first")));
+		assert_eq!(label(&pattern, "/path/to/files/sub/access_first.log"), None);
+	}
+	
+	#[test]
+	fn valid_with_relative_file_name_wildcard() {
+		let pattern = parse_wildcards("*.log");
+		assert_eq!(pattern.base_folder, "");
+		assert_eq!(label(&pattern, "access.log"), Some(String::from("access")));
 	}
 	
 	#[test]
 	fn valid_with_folder_wildcard() {
-		assert!(matches!(parse_log_file_pattern_from_str("/path/to/*/files/access.log"), Ok(LogFilePattern::WithFolderNameWildcard(pattern)) if pattern.path_prefix == "/path/to/" && pattern.path_suffix == "files/access.log"));
+		let pattern = parse_wildcards("/path/to/*/files/access.log");
+		assert_eq!(pattern.base_folder, "/path/to");
+		assert_eq!(pattern.max_depth, Some(2));
+		assert_eq!(label(&pattern, "/path/to/first/files/access.log"), Some(String::from("first")));
+	}
+	
+	#[test]
+	fn valid_with_folder_wildcard_prefix() {
+		let pattern = parse_wildcards("/path/*abc/to/files/access.log");
+		assert_eq!(pattern.base_folder, "/path");
+		assert_eq!(label(&pattern, "/path/xyzabc/to/files/access.log"), Some(String::from("xyz")));
+		assert_eq!(label(&pattern, "/path/xyz/to/files/access.log"), None);
+	}
+	
+	#[test]
+	fn valid_with_multiple_wildcards() {
+		let pattern = parse_wildcards("/path/*/to/files/*.log");
+		assert_eq!(label(&pattern, "/path/first/to/files/access.log"), Some(String::from("first/access")));
+	}
+	
+	#[test]
+	fn valid_with_single_character_wildcard_and_class() {
+		let pattern = parse_wildcards("/path/site?/[a-c]_[!0-9].log");
+		assert_eq!(label(&pattern, "/path/site1/b_x.log"), Some(String::from("1/b/x")));
+		assert_eq!(label(&pattern, "/path/site12/b_x.log"), None);
+		assert_eq!(label(&pattern, "/path/site1/d_x.log"), None);
+		assert_eq!(label(&pattern, "/path/site1/b_1.log"), None);
+	}
+	
+	#[test]
+	fn valid_with_recursive_wildcard() {
+		let pattern = parse_wildcards("/path/**/access.log");
+		assert_eq!(pattern.base_folder, "/path");
+		assert_eq!(pattern.max_depth, None);
+		assert_eq!(label(&pattern, "/path/access.log"), Some(String::new()));
+		assert_eq!(label(&pattern, "/path/a/b/access.log"), Some(String::from("a/b")));
+		
+		let pattern = parse_wildcards("/path/**");
+		assert_eq!(label(&pattern, "/path/a/b/access.log"), Some(String::from("a/b/access.log")));
+	}
+	
+	#[test]
+	fn valid_with_label_groups() {
+		let pattern = parse_wildcards("/srv/(*)/logs/**/*.access.log");
+		assert_eq!(label(&pattern, "/srv/first/logs/2024/01/main.access.log"), Some(String::from("first")));
+		
+		let pattern = parse_wildcards("/srv/(site_*)/logs/(*).log");
+		assert_eq!(label(&pattern, "/srv/site_a/logs/access.log"), Some(String::from("site_a/access")));
+		
+		let pattern = parse_wildcards("/srv/\\(*\\)/access.log");
+		assert_eq!(label(&pattern, "/srv/(first)/access.log"), Some(String::from("first")));
+	}
+	
+	#[test]
+	fn literal_parentheses_next_to_wildcard() {
+		let pattern = parse_wildcards("/srv/site (old)/*.log");
+		assert_eq!(label(&pattern, "/srv/site (old)/access.log"), Some(String::from("access")));
+		assert_eq!(label(&pattern, "/srv/site old/access.log"), None);
+		
+		let pattern = parse_wildcards("/srv/(*)/backup (old)/*.log");
+		assert_eq!(label(&pattern, "/srv/first/backup (old)/access.log"), Some(String::from("first")));
+		
+		let pattern = parse_wildcards("/srv/site[()]/*.log");
+		assert_eq!(label(&pattern, "/srv/site(/access.log"), Some(String::from("(/access")));
+	}
+	
+	#[test]
+	fn invalid_wildcards() {
+		assert!(matches!(parse_log_file_pattern_from_str("/path/a**/access.log"), Err(err) if err.to_string() == "Recursive wildcard must be a whole folder name"));
+		assert!(matches!(parse_log_file_pattern_from_str("/path/**b/access.log"), Err(err) if err.to_string() == "Recursive wildcard must be a whole folder name"));
+		assert!(matches!(parse_log_file_pattern_from_str("/path/[a-z/access.log"), Err(err) if err.to_string() == "Pattern has an unclosed character class"));
+		assert!(matches!(parse_log_file_pattern_from_str("/path/((*))/access.log"), Err(err) if err.to_string() == "Pattern has nested parentheses"));
+		assert!(matches!(parse_log_file_pattern_from_str("/path/(*/access.log"), Err(err) if err.to_string() == "Pattern has unmatched parentheses"));
+		assert!(matches!(parse_log_file_pattern_from_str("/path/*)/access.log"), Err(err) if err.to_string() == "Pattern has unmatched parentheses"));
+	}
+	
+	#[test]
+	fn valid_with_regex() {
+		let pattern = parse_log_file_pattern_from_str("regex:/logs/(?P<site>[^/]+)/(?P<env>prod|stage)/access\\.log");
+		assert!(matches!(pattern, Ok(LogFilePattern::WithRegex(pattern)) if pattern.base_folder == "/logs"));
+	}
+	
+	#[test]
+	fn regex_base_folder() {
+		let base_folder = |pattern: &str| match parse_log_file_pattern_from_str(pattern) {
+			Ok(LogFilePattern::WithRegex(pattern)) => pattern.base_folder,
+			_ => panic!("Pattern is not a regex pattern"),
+		};
+		
+		assert_eq!(base_folder("regex:^/var/log/apache2/(?P<file>.+)\\.log"), "/var/log/apache2");
+		assert_eq!(base_folder("regex:/var/www/example\\.com/logs/access\\.log"), "/var/www");
+		assert_eq!(base_folder("regex:/(?P<file>[^/]+)/access\\.log"), "/");
+		assert_eq!(base_folder("regex:(?P<file>[^/]+)\\.log"), "");
+		assert_eq!(base_folder("regex:logs/(?P<file>[^/]+)\\.log"), "logs");
+		assert_eq!(base_folder("regex:(?i)/var/log/x\\.log"), "/");
+		assert_eq!(base_folder("regex:(?i)logs/x\\.log"), "");
+		assert_eq!(base_folder("regex:(?:/var|/srv)/log/x\\.log"), "/");
+		assert_eq!(base_folder("regex:/var/log/a\\.log|/srv/b\\.log"), "/");
+		assert_eq!(base_folder("regex:^/var/log/a\\.log|^/var/log/old/b\\.log"), "/var/log");
+		assert_eq!(base_folder("regex:/var/log/a\\.log|logs/b\\.log"), "");
+		assert_eq!(base_folder("regex:/var/log/[|]\\.log"), "/var/log");
+		assert_eq!(base_folder("regex:/var/log/\\|\\.log"), "/var/log");
+	}
+	
+	#[test]
+	fn regex_search_with_alternatives() {
+		let folder = create_test_folder("regex_alternatives");
+		fs::create_dir_all(folder.join("a")).unwrap();
+		fs::create_dir_all(folder.join("b")).unwrap();
+		fs::write(folder.join("a/access.log"), "").unwrap();
+		fs::write(folder.join("b/ACCESS.log"), "").unwrap();
+		
+		let pattern = format!("regex:{0}/a/access\\.log|{0}/b/ACCESS\\.log", folder.display());
+		assert_eq!(search(&pattern), vec![folder.join("a/access.log"), folder.join("b/ACCESS.log")]);
+		
+		fs::remove_dir_all(&folder).unwrap();
+	}
+	
+	#[test]
+	fn regex_labels() {
+		let Ok(LogFilePattern::WithRegex(pattern)) = parse_log_file_pattern_from_str("regex:/logs/(?P<site>[^/]+)/(?P<env>prod|stage)/(?P<file>[a-z]+)\\.log") else {
+			panic!("Pattern is not a regex pattern");
+		};
+		
+		let log_file = pattern.match_path("/logs/example.com/prod/access.log").unwrap();
+		assert_eq!(log_file.label, "access");
+		assert_eq!(log_file.pattern_labels, vec![(String::from("site"), String::from("example.com")), (String::from("env"), String::from("prod"))]);
+		
+		assert!(pattern.match_path("/logs/example.com/dev/access.log").is_none());
+		assert!(pattern.match_path("/logs/example.com/prod/access.log.1").is_none());
+	}
+	
+	#[test]
+	fn invalid_regex() {
+		assert!(matches!(parse_log_file_pattern_from_str("regex:"), Err(err) if err.to_string() == "Path is empty"));
+		assert!(matches!(parse_log_file_pattern_from_str("regex:/logs/(.log"), Err(err) if err.to_string() == "Invalid regular expression: /logs/(.log"));
+		assert!(matches!(parse_log_file_pattern_from_str("regex:/logs/(?P<status>.+)"), Err(err) if err.to_string() == "Capture group name is reserved: status"));
+		assert!(matches!(parse_log_file_pattern_from_str("regex:/logs/(?P<__name__>.+)"), Err(err) if err.to_string() == "Capture group name is reserved: __name__"));
+		assert!(matches!(parse_log_file_pattern_from_str("regex:/logs/(?P<a.b>.+)"), Err(err) if err.to_string() == "Capture group name is not a valid label name: a.b"));
+		assert!(matches!(parse_log_file_pattern_from_str("regex:/logs/(?P<a[0]>.+)"), Err(err) if err.to_string() == "Capture group name is not a valid label name: a[0]"));
+	}
+	
+	#[test]
+	#[cfg(unix)]
+	fn search_skips_unreadable_folders() {
+		use std::os::unix::fs::PermissionsExt;
+		
+		let folder = create_test_folder("unreadable");
+		fs::create_dir_all(folder.join("a")).unwrap();
+		fs::create_dir_all(folder.join("b")).unwrap();
+		fs::write(folder.join("a/access.log"), "").unwrap();
+		fs::write(folder.join("b/access.log"), "").unwrap();
+		fs::set_permissions(folder.join("b"), fs::Permissions::from_mode(0o000)).unwrap();
+		
+		let paths = search(&format!("{}/*/access.log", folder.display()));
+		
+		// Permissions are not enforced for root, in which case the folder is searched as well.
+		assert!(paths.contains(&folder.join("a/access.log")));
+		
+		fs::set_permissions(folder.join("b"), fs::Permissions::from_mode(0o755)).unwrap();
+		fs::remove_dir_all(&folder).unwrap();
+	}
+	
+	#[test]
+	fn folder_filters() {
+		let filter = |prefix: &str, exact: bool| FolderFilter { prefix: String::from(prefix), exact };
+		
+		assert_eq!(parse_wildcards("/srv/site_*/logs/*.log").folder_filters, vec![filter("site_", false), filter("logs", true)]);
+		assert_eq!(parse_wildcards("/srv/*/logs/**/access.log").folder_filters, vec![filter("", false), filter("logs", true)]);
+		
+		let regex_filters = |pattern: &str| match parse_log_file_pattern_from_str(pattern) {
+			Ok(LogFilePattern::WithRegex(pattern)) => pattern.folder_filters,
+			_ => panic!("Pattern is not a regex pattern"),
+		};
+		
+		assert_eq!(regex_filters("regex:/var/log/site[0-9]{1,3}/access\\.log"), vec![filter("site", false)]);
+		assert_eq!(regex_filters("regex:/var/log/sites?/access\\.log"), vec![filter("site", false)]);
+		assert_eq!(regex_filters("regex:/var/log/(?P<file>.+)\\.log"), vec![filter("", false)]);
+	}
+	
+	#[test]
+	fn search_prunes_folders() {
+		let folder = create_test_folder("prune");
+		fs::create_dir_all(folder.join("site_a/logs/2024")).unwrap();
+		fs::create_dir_all(folder.join("site_b/other")).unwrap();
+		fs::create_dir_all(folder.join("backup/site_c/logs")).unwrap();
+		fs::write(folder.join("site_a/logs/2024/access.log"), "").unwrap();
+		fs::write(folder.join("site_b/other/access.log"), "").unwrap();
+		fs::write(folder.join("backup/site_c/logs/access.log"), "").unwrap();
+		
+		assert_eq!(search(&format!("{}/site_*/logs/**/access.log", folder.display())), vec![folder.join("site_a/logs/2024/access.log")]);
+		assert_eq!(search(&format!("regex:{}/site_[a-z]/.+/access\\.log", folder.display())), vec![folder.join("site_a/logs/2024/access.log"), folder.join("site_b/other/access.log")]);
+		
+		fs::remove_dir_all(&folder).unwrap();
 	}
 }
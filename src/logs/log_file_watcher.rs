@@ -1,159 +1,462 @@
-use std::cmp::max;
-use std::path::PathBuf;
-use std::sync::Arc;
+use std::collections::{HashMap, HashSet};
+use std::{fs, mem};
+use std::io;
+use std::io::SeekFrom;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant, SystemTime};
 
 use anyhow::{anyhow, bail, Context, Result};
-use notify::{Event, EventKind};
-use notify::event::{CreateKind, ModifyKind};
 use tokio::fs::File;
-use tokio::io::{AsyncBufReadExt, BufReader, Lines};
-use tokio::sync::mpsc;
-use tokio::sync::mpsc::Receiver;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncSeekExt, BufReader};
+use tokio::task::JoinHandle;
 
-use crate::logs::filesystem_watcher::{FsEventCallbacks, FsWatcher};
+use crate::logs::access_log_format::AccessLogFormat;
+use crate::logs::access_log_parser::AccessLogLineParts;
+use crate::logs::error_log_parser::ErrorLogLineParts;
+use crate::logs::{LogFileSource, STATE_CHECKPOINT_INTERVAL, WatcherSettings};
+use crate::logs::filesystem_watcher::{CoalescedFsEvent, FsEventCallbacks, FsEventReceiver, FsWatcher};
 use crate::logs::log_file_pattern::LogFilePath;
-use crate::metrics::Metrics;
+use crate::logs::read_positions::{FileIdentity, ReadPosition, ReadPositions};
+use crate::logs::request_line_parser::RequestLineParts;
+use crate::metrics::{ErrorCodeLabels, ErrorLabels, FileLabels, Metrics, ParseErrorLabels, PatternLabels, RequestLabels, RouteLabels, RouteRequestLabels};
+use crate::route_rules::FALLBACK_ROUTE;
 
-#[derive(Copy, Clone, PartialEq)]
+#[derive(Clone)]
 pub enum LogFileKind {
-	Access,
+	Access(Arc<AccessLogFormat>),
 	Error,
 }
 
+impl LogFileKind {
+	pub fn name(&self) -> &'static str {
+		match self {
+			LogFileKind::Access(_) => "access log",
+			LogFileKind::Error => "error log",
+		}
+	}
+}
+
 struct LogFileMetadata {
 	pub kind: LogFileKind,
 	pub label: String,
+	pub pattern_labels: PatternLabels,
 }
 
 impl LogFileMetadata {
-	fn get_label_set(&self) -> [(&'static str, String); 1] {
-		[("file", self.label.clone())]
+	fn new(log_file: LogFilePath, kind: LogFileKind) -> (PathBuf, LogFileMetadata) {
+		let pattern_labels = PatternLabels::new(log_file.pattern_labels);
+		(log_file.path, LogFileMetadata { kind, label: log_file.label, pattern_labels })
+	}
+	
+	fn get_label_set(&self) -> FileLabels {
+		FileLabels { file: self.label.clone(), pattern_labels: self.pattern_labels.clone() }
 	}
 }
 
 pub struct LogWatcherConfiguration {
-	files: Vec<(PathBuf, LogFileMetadata)>,
+	settings: WatcherSettings,
+	sources: Vec<LogFileSource>,
+	files: Vec<(PathBuf, LogFileMetadata, StartPosition)>,
+	canonical_paths: HashSet<PathBuf>,
 }
 
 impl LogWatcherConfiguration {
-	pub fn new() -> LogWatcherConfiguration {
-		LogWatcherConfiguration { files: Vec::new() }
+	pub fn new(settings: WatcherSettings) -> LogWatcherConfiguration {
+		LogWatcherConfiguration { settings, sources: Vec::new(), files: Vec::new(), canonical_paths: HashSet::new() }
 	}
 	
-	fn count_files_of_kind(&self, kind: LogFileKind) -> usize {
-		return self.files.iter().filter(|(_, metadata)| metadata.kind == kind).count();
+	fn count_files_of_kind(&self, predicate: fn(&LogFileKind) -> bool) -> usize {
+		self.files.iter().filter(|(_, metadata, _)| predicate(&metadata.kind)).count()
 	}
 	
-	pub fn add_file(&mut self, log_file: LogFilePath, kind: LogFileKind) {
-		let path = log_file.path;
-		let label = log_file.label;
-		let metadata = LogFileMetadata { kind, label };
-		self.files.push((path, metadata));
+	/// Adds a source that is searched again for new files while watching.
+	pub(super) fn add_source(&mut self, source: LogFileSource) {
+		self.sources.push(source);
+	}
+	
+	/// Adds a file to watch. Fails if the file was already added, even through a different path that
+	/// resolves to the same file, because its lines would be counted twice.
+	pub fn add_file(&mut self, log_file: LogFilePath, kind: LogFileKind, start_position: StartPosition) -> Result<()> {
+		if !self.canonical_paths.insert(canonical_path(&log_file.path)) {
+			bail!("File is matched more than once: {}", log_file.path.display());
+		}
+		
+		let (path, metadata) = LogFileMetadata::new(log_file, kind);
+		self.files.push((path, metadata, start_position));
+		Ok(())
 	}
 	
-	pub async fn start(self, metrics: &Metrics) -> Result<()> {
+	/// Starts watching all added files. Returns the read positions of the watched files if a state file is configured,
+	/// so that they can be saved one last time when the exporter stops.
+	pub async fn start(self, metrics: &Metrics) -> Result<Option<Arc<ReadPositions>>> {
 		if self.files.is_empty() {
 			bail!("No log files provided");
 		}
 		
-		println!("[LogWatcher] Watching {} access log file(s) and {} error log file(s).", self.count_files_of_kind(LogFileKind::Access), self.count_files_of_kind(LogFileKind::Error));
+		println!("[LogWatcher] Watching {} access log file(s) and {} error log file(s).", self.count_files_of_kind(|kind| matches!(kind, LogFileKind::Access(_))), self.count_files_of_kind(|kind| matches!(kind, LogFileKind::Error)));
 		
-		struct PreparedFile {
-			path: PathBuf,
-			metadata: LogFileMetadata,
-			fs_event_receiver: Receiver<Event>,
+		let read_positions = match &self.settings.state_file {
+			Some(state_file) => Some(Arc::new(ReadPositions::load(state_file)?)),
+			None => None,
+		};
+		
+		let fs_callbacks = FsEventCallbacks::new();
+		let fs_watcher = FsWatcher::new(fs_callbacks.clone(), self.settings.backend, self.settings.poll_interval).context("Could not create filesystem watcher")?;
+		let read_files = self.settings.rescan_interval.map(|_| ReadFiles::default());
+		let context = LogWatcherContext { metrics: metrics.clone(), fs_watcher: Arc::new(fs_watcher), read_positions: read_positions.clone(), rotation_grace_period: self.settings.rotation_grace_period, read_files };
+		let mut watchers = RunningLogWatchers { context, fs_callbacks, files: HashMap::new(), watched_folders: HashSet::new() };
+		
+		for (path, metadata, start_position) in self.files {
+			watchers.start_file(canonical_path(&path), path, metadata, start_position, None).await?;
 		}
 		
-		let mut prepared_files = Vec::new();
-		let mut fs_callbacks = FsEventCallbacks::new();
+		if let Some(rescan_interval) = self.settings.rescan_interval {
+			tokio::spawn(watchers.rescan_periodically(Arc::from(self.sources), rescan_interval));
+		}
 		
-		for (path, metadata) in self.files {
-			let (fs_event_sender, fs_event_receiver) = mpsc::channel(20);
-			fs_callbacks.register(&path, fs_event_sender);
-			prepared_files.push(PreparedFile { path, metadata, fs_event_receiver });
+		if let Some(read_positions) = &read_positions {
+			tokio::spawn(Arc::clone(read_positions).save_periodically(STATE_CHECKPOINT_INTERVAL));
 		}
 		
-		let fs_watcher = FsWatcher::new(fs_callbacks).context("Could not create filesystem watcher")?;
+		Ok(read_positions)
+	}
+}
+
+fn canonical_path(path: &Path) -> PathBuf {
+	fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// Shared state and settings used by the watchers of all log files.
+#[derive(Clone)]
+struct LogWatcherContext {
+	metrics: Metrics,
+	fs_watcher: Arc<FsWatcher>,
+	read_positions: Option<Arc<ReadPositions>>,
+	rotation_grace_period: Option<Duration>,
+	/// Files that are read by the log watchers, which are only needed if new files are searched for.
+	read_files: Option<ReadFiles>,
+}
+
+/// Files read by log watchers, keyed by their identity. A file that was renamed by rotation may be found again
+/// under its new name if the name matches a pattern, in which case it must not be read a second time.
+#[derive(Clone, Default)]
+struct ReadFiles {
+	files: Arc<Mutex<HashMap<FileIdentity, ReadFileStatus>>>,
+}
+
+#[derive(Copy, Clone, Eq, PartialEq)]
+enum ReadFileStatus {
+	/// The file is read as a watched file, or as a rotated file during the grace period.
+	Reading,
+	/// The grace period of the rotated file has ended after reading it up to the offset. The status is kept until
+	/// the end of the next search, which is flagged by `searched`, so that the file can be found under its new name.
+	Finished { offset: u64, searched: bool },
+}
+
+impl ReadFiles {
+	fn set_reading(&self, identity: Option<FileIdentity>) {
+		if let Some(identity) = identity {
+			self.files.lock().unwrap_or_else(PoisonError::into_inner).insert(identity, ReadFileStatus::Reading);
+		}
+	}
+	
+	fn set_finished(&self, identity: Option<FileIdentity>, offset: u64) {
+		if let Some(identity) = identity {
+			self.files.lock().unwrap_or_else(PoisonError::into_inner).insert(identity, ReadFileStatus::Finished { offset, searched: false });
+		}
+	}
+	
+	fn remove(&self, identity: Option<FileIdentity>) {
+		if let Some(identity) = identity {
+			self.files.lock().unwrap_or_else(PoisonError::into_inner).remove(&identity);
+		}
+	}
+	
+	fn status(&self, identity: FileIdentity) -> Option<ReadFileStatus> {
+		self.files.lock().unwrap_or_else(PoisonError::into_inner).get(&identity).copied()
+	}
+	
+	/// Forgets finished files that were not found by the previous search either, because their new names do not
+	/// match any pattern.
+	fn finish_search(&self) {
+		self.files.lock().unwrap_or_else(PoisonError::into_inner).retain(|_, status| match status {
+			ReadFileStatus::Reading => true,
+			ReadFileStatus::Finished { searched, .. } => !mem::replace(searched, true),
+		});
+	}
+}
+
+/// Log files that are being watched, keyed by their canonical path.
+struct RunningLogWatchers {
+	context: LogWatcherContext,
+	fs_callbacks: FsEventCallbacks,
+	files: HashMap<PathBuf, RunningLogWatcher>,
+	/// Folders that are already registered with the filesystem watcher.
+	watched_folders: HashSet<PathBuf>,
+}
+
+struct RunningLogWatcher {
+	path: PathBuf,
+	file_labels: FileLabels,
+	task: JoinHandle<()>,
+	/// Whether the file did not exist during the last search. Files are only retired when they are missing
+	/// in two consecutive searches, so that a file which is being rotated is not retired.
+	missing: bool,
+}
+
+impl RunningLogWatchers {
+	/// Starts watching a file. A resume position takes precedence over the position saved in the state file.
+	async fn start_file(&mut self, canonical_path: PathBuf, path: PathBuf, metadata: LogFileMetadata, start_position: StartPosition, resume_position: Option<ReadPosition>) -> Result<()> {
+		if !path.is_absolute() {
+			bail!("Path is not absolute: {}", path.to_string_lossy());
+		}
+		
+		let parent_path = path.parent().ok_or_else(|| anyhow!("Path has no parent: {}", path.to_string_lossy()))?;
+		if !self.watched_folders.contains(parent_path) {
+			self.context.fs_watcher.watch_folder(parent_path).await.with_context(|| format!("Could not create filesystem watcher for directory: {}", parent_path.to_string_lossy()))?;
+			self.watched_folders.insert(parent_path.to_path_buf());
+		}
+		
+		let fs_event_receiver = self.fs_callbacks.register(&path);
+		
+		let file_labels = metadata.get_label_set();
+		let resume_position = resume_position.or_else(|| self.context.read_positions.as_ref().and_then(|read_positions| read_positions.saved_position(&path)));
+		let log_watcher = LogWatcher::create(path.clone(), metadata, &self.context, fs_event_receiver, start_position, resume_position).await;
+		let log_watcher = match log_watcher {
+			Ok(log_watcher) => log_watcher,
+			Err(e) => {
+				self.fs_callbacks.unregister(&path);
+				return Err(e.context(format!("Could not watch log file: {}", path.to_string_lossy())));
+			}
+		};
+		
+		let task = tokio::spawn(log_watcher.watch());
+		self.files.insert(canonical_path, RunningLogWatcher { path, file_labels, task, missing: false });
+		Ok(())
+	}
+	
+	async fn rescan_periodically(mut self, sources: Arc<[LogFileSource]>, rescan_interval: Duration) {
+		let mut interval = tokio::time::interval(rescan_interval);
+		interval.tick().await;
+		
+		loop {
+			interval.tick().await;
+			self.rescan(&sources).await;
+		}
+	}
+	
+	/// Searches for new files to watch, and retires watched files that no longer exist.
+	async fn rescan(&mut self, sources: &Arc<[LogFileSource]>) {
+		let searched_sources = Arc::clone(sources);
+		let watched_paths = self.files.iter().map(|(canonical_path, file)| (canonical_path.clone(), file.path.clone())).collect::<Vec<_>>();
 		
-		for file in &prepared_files {
-			let file_path = &file.path;
-			if !file_path.is_absolute() {
-				bail!("Path is not absolute: {}", file_path.to_string_lossy());
+		// Searching folders and checking files blocks, so it must not run on the runtime thread that also serves metrics.
+		let rescan = tokio::task::spawn_blocking(move || {
+			let search_results = searched_sources.iter().map(|source| {
+				source.search().map(|log_files| log_files.into_iter().map(|log_file| (canonical_path(&log_file.path), log_file)).collect())
+			}).collect::<Vec<_>>();
+			
+			let missing_paths = watched_paths.into_iter().filter(|(_, path)| !path.exists()).map(|(canonical_path, _)| canonical_path).collect::<HashSet<_>>();
+			(search_results, missing_paths)
+		});
+		
+		match rescan.await {
+			Ok((search_results, missing_paths)) => {
+				self.start_new_files(sources, search_results).await;
+				self.retire_missing_files(&missing_paths).await;
 			}
+			Err(e) => println!("[LogWatcher] Could not search for new log files: {}", e),
+		}
+	}
+	
+	/// Starts watching the files found by searching the sources, with the canonical path of each file.
+	async fn start_new_files(&mut self, sources: &[LogFileSource], search_results: Vec<Result<Vec<(PathBuf, LogFilePath)>>>) {
+		for (source, search_result) in sources.iter().zip(search_results) {
+			let log_files = match search_result {
+				Ok(log_files) => log_files,
+				Err(e) => {
+					println!("[LogWatcher] {:#}", e);
+					continue;
+				}
+			};
 			
-			let parent_path = file_path.parent().ok_or_else(|| anyhow!("Path has no parent: {}", file_path.to_string_lossy()))?;
-			fs_watcher.watch(parent_path).await.with_context(|| format!("Could not create filesystem watcher for directory: {}", parent_path.to_string_lossy()))?;
+			for (canonical_path, log_file) in log_files {
+				if self.files.contains_key(&canonical_path) {
+					continue;
+				}
+				
+				let identity = tokio::fs::metadata(&log_file.path).await.ok().and_then(|metadata| FileIdentity::of(&metadata));
+				let status = identity.zip(self.context.read_files.as_ref()).and_then(|(identity, read_files)| read_files.status(identity));
+				
+				let resume_position = match (status, identity) {
+					(Some(ReadFileStatus::Reading), _) => {
+						println!("[LogWatcher] Not watching new file yet, because it is still read as a rotated file: {}", log_file.path.to_string_lossy());
+						continue;
+					}
+					(Some(ReadFileStatus::Finished { offset, .. }), Some(identity)) => Some(ReadPosition { identity, offset }),
+					_ => None,
+				};
+				
+				println!("[LogWatcher] Found new {} file: {} (label \"{}\")", source.kind().name(), log_file.path.to_string_lossy(), log_file.label);
+				
+				let (path, metadata) = LogFileMetadata::new(log_file, source.kind().clone());
+				if let Err(e) = self.start_file(canonical_path, path, metadata, StartPosition::Beginning, resume_position).await {
+					println!("[LogWatcher] {:#}", e);
+				}
+			}
 		}
 		
-		let fs_watcher = Arc::new(fs_watcher);
+		if let Some(read_files) = &self.context.read_files {
+			read_files.finish_search();
+		}
+	}
+	
+	async fn retire_missing_files(&mut self, missing_paths: &HashSet<PathBuf>) {
+		let mut retired_paths = Vec::new();
+		
+		for (canonical_path, file) in &mut self.files {
+			if !missing_paths.contains(canonical_path) {
+				file.missing = false;
+			} else if file.missing {
+				retired_paths.push(canonical_path.clone());
+			} else {
+				file.missing = true;
+			}
+		}
 		
-		for file in prepared_files {
-			let label_set = file.metadata.get_label_set();
-			let _ = metrics.requests_total.get_or_create(&label_set);
-			let _ = metrics.errors_total.get_or_create(&label_set);
+		for canonical_path in retired_paths {
+			let Some(file) = self.files.remove(&canonical_path) else {
+				continue;
+			};
 			
-			let log_watcher = LogWatcher::create(file.path.clone(), file.metadata, metrics.clone(), Arc::clone(&fs_watcher), file.fs_event_receiver);
-			let log_watcher = log_watcher.await.with_context(|| format!("Could not watch log file: {}", file.path.to_string_lossy()))?;
+			println!("[LogWatcher] File deleted, stopping log watcher for: {}", file.path.to_string_lossy());
+			
+			file.task.abort();
+			self.fs_callbacks.unregister(&file.path);
+			
+			if let Some(read_positions) = &self.context.read_positions {
+				read_positions.remove(&file.path);
+			}
 			
-			tokio::spawn(log_watcher.watch());
+			if let Err(e) = self.context.fs_watcher.unwatch(&file.path).await {
+				println!("[LogWatcher] Could not remove filesystem watcher for file \"{}\": {}", file.path.to_string_lossy(), e);
+			}
+			
+			// Another file may have the same labels, in which case its series must be kept.
+			if !self.files.values().any(|other| other.file_labels == file.file_labels) {
+				let removed_series = self.context.metrics.remove_file_series(&file.file_labels);
+				println!("[LogWatcher] Removed {} series of deleted file: {}", removed_series, file.path.to_string_lossy());
+			}
 		}
-		
-		Ok(())
 	}
 }
 
+/// Where to start reading a log file when watching starts. Files found after the exporter has started are always
+/// read from the beginning, unless they were already read under a different name before rotation, and a position
+/// saved in the state file takes precedence.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum StartPosition {
+	Beginning,
+	End,
+}
+
 struct LogWatcher {
 	state: LogWatchingState,
 	processor: LogLineProcessor,
-	fs_event_receiver: Receiver<Event>,
+	fs_event_receiver: FsEventReceiver,
+	start_position: StartPosition,
+	resume_position: Option<ReadPosition>,
+	read_positions: Option<Arc<ReadPositions>>,
+	rotation_grace_period: Option<Duration>,
+	rotated_file: Option<RotatedFile>,
+	read_files: Option<ReadFiles>,
+}
+
+/// A file that was renamed by rotation, and is still read until the grace period ends, because Apache may keep
+/// writing to it until it reopens its log files.
+struct RotatedFile {
+	state: LogWatchingState,
+	deadline: Instant,
 }
 
 impl LogWatcher {
-	async fn create(path: PathBuf, metadata: LogFileMetadata, metrics: Metrics, fs_watcher: Arc<FsWatcher>, fs_event_receiver: Receiver<Event>) -> Result<Self> {
-		let state = LogWatchingState::initialize(path.clone(), fs_watcher).await?;
-		let processor = LogLineProcessor { path, metadata, metrics };
-		Ok(LogWatcher { state, processor, fs_event_receiver })
+	/// How often a rotated file is read, since filesystem events for its new name are not received.
+	const ROTATED_FILE_POLL_INTERVAL: Duration = Duration::from_secs(1);
+	
+	async fn create(path: PathBuf, metadata: LogFileMetadata, context: &LogWatcherContext, fs_event_receiver: FsEventReceiver, start_position: StartPosition, resume_position: Option<ReadPosition>) -> Result<Self> {
+		let state = LogWatchingState::initialize(path.clone(), Arc::clone(&context.fs_watcher)).await?;
+		let processor = LogLineProcessor { path, metadata, metrics: context.metrics.clone() };
+		let read_positions = context.read_positions.clone();
+		let rotation_grace_period = context.rotation_grace_period;
+		let read_files = context.read_files.clone();
+		
+		if let Some(read_files) = &read_files {
+			read_files.set_reading(state.identity);
+		}
+		
+		Ok(LogWatcher { state, processor, fs_event_receiver, start_position, resume_position, read_positions, rotation_grace_period, rotated_file: None, read_files })
 	}
 	
 	async fn watch(mut self) {
-		while let Ok(Some(_)) = self.state.lines.next_line().await {
-			// Skip lines that already existed.
-		}
+		let path = self.processor.path.clone();
 		
-		let path = &self.processor.path;
+		match self.resume_position {
+			Some(resume_position) if self.state.resume(resume_position).await => {
+				println!("[LogWatcher] Resuming from byte {} of file: {}", resume_position.offset, path.to_string_lossy());
+			}
+			_ => {
+				if self.start_position == StartPosition::End {
+					while let Ok(Some(_)) = self.state.next_line().await {
+						// Skip lines that already existed.
+					}
+				}
+			}
+		}
 		
 		'read_loop:
 		loop {
-			if !self.processor.process_lines(&mut self.state.lines).await {
+			self.read_rotated_file().await;
+			
+			if self.state.is_truncated().await {
+				println!("[LogWatcher] File truncated: {}", path.to_string_lossy());
+				self.processor.count_rotation();
+				
+				if let Err(e) = self.state.seek(0).await {
+					println!("[LogWatcher] Could not seek in file \"{}\": {}", path.to_string_lossy(), e);
+					break 'read_loop;
+				}
+			}
+			
+			if !self.processor.process_lines(&mut self.state).await {
 				break 'read_loop;
 			}
 			
+			self.update_read_position();
+			
 			'event_loop:
 			loop {
-				let mut next_event = CoalescedFsEvent::None;
-				
-				match self.fs_event_receiver.recv().await {
-					None => break 'read_loop,
-					Some(event) => {
-						next_event = next_event.merge(event);
-						
-						while let Ok(event) = self.fs_event_receiver.try_recv() {
-							next_event = next_event.merge(event);
+				let next_event = if self.rotated_file.is_some() {
+					tokio::select! {
+						event = self.fs_event_receiver.recv() => event,
+						_ = tokio::time::sleep(Self::ROTATED_FILE_POLL_INTERVAL) => {
+							self.read_rotated_file().await;
+							continue 'event_loop;
 						}
 					}
-				}
+				} else {
+					self.fs_event_receiver.recv().await
+				};
 				
 				match next_event {
 					CoalescedFsEvent::None => continue 'event_loop,
 					CoalescedFsEvent::NewData => continue 'read_loop,
 					CoalescedFsEvent::NewFile => {
-						println!("[LogWatcher] File recreated: {}", path.to_string_lossy());
-						
-						if !self.processor.process_lines(&mut self.state.lines).await {
+						if !self.processor.process_lines(&mut self.state).await {
 							break 'read_loop;
 						}
 						
-						self.state = match self.state.reinitialize().await {
+						let mut new_state = match self.state.reopen().await {
 							Ok(state) => state,
 							Err(e) => {
 								println!("Could not re-watch log file \"{}\": {}", path.to_string_lossy(), e);
@@ -161,11 +464,22 @@ impl LogWatcher {
 							}
 						};
 						
-						while let Ok(Some(_)) = self.state.lines.next_line().await {
-							// There are occasional spurious file creation events, so reading
-							// from the beginning would read lines that were already counted.
+						if self.state.is_same_file(&mut new_state).await {
+							// There are occasional spurious file creation events, in which case
+							// the file is still the same and reading continues where it left off.
+							continue 'read_loop;
+						}
+						
+						println!("[LogWatcher] File recreated: {}", path.to_string_lossy());
+						self.processor.count_rotation();
+						
+						if let Some(read_files) = &self.read_files {
+							read_files.set_reading(new_state.identity);
 						}
 						
+						let rotated_state = mem::replace(&mut self.state, new_state);
+						self.start_reading_rotated_file(rotated_state).await;
+						
 						continue 'read_loop;
 					}
 				}
@@ -174,52 +488,250 @@ impl LogWatcher {
 		
 		println!("[LogWatcher] Stopping log watcher for: {}", path.to_string_lossy());
 	}
+	
+	fn update_read_position(&self) {
+		if let (Some(read_positions), Some(position)) = (&self.read_positions, self.state.read_position()) {
+			read_positions.update(&self.state.path, position);
+		}
+	}
+	
+	/// Keeps reading a file that was replaced by a new file until the grace period ends. A previously rotated file
+	/// is read one last time and closed.
+	async fn start_reading_rotated_file(&mut self, state: LogWatchingState) {
+		if let Some(mut rotated_file) = self.rotated_file.take() {
+			self.processor.process_lines(&mut rotated_file.state).await;
+			self.finish_reading_rotated_file(&rotated_file.state);
+		}
+		
+		match self.rotation_grace_period {
+			Some(grace_period) => self.rotated_file = Some(RotatedFile { state, deadline: Instant::now() + grace_period }),
+			None => self.finish_reading_rotated_file(&state),
+		}
+	}
+	
+	async fn read_rotated_file(&mut self) {
+		let Some(rotated_file) = &mut self.rotated_file else {
+			return;
+		};
+		
+		let is_readable = self.processor.process_lines(&mut rotated_file.state).await;
+		
+		if !is_readable || Instant::now() >= rotated_file.deadline {
+			println!("[LogWatcher] Stopped reading rotated file: {}", self.processor.path.to_string_lossy());
+			
+			if let Some(rotated_file) = self.rotated_file.take() {
+				self.finish_reading_rotated_file(&rotated_file.state);
+			}
+		}
+	}
+	
+	fn finish_reading_rotated_file(&self, state: &LogWatchingState) {
+		if let Some(read_files) = &self.read_files {
+			read_files.set_finished(state.identity, state.position);
+		}
+	}
 }
 
-#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd)]
-enum CoalescedFsEvent {
-	None = 0,
-	NewData = 1,
-	NewFile = 2,
-}
-
-impl CoalescedFsEvent {
-	fn merge(self, event: Event) -> CoalescedFsEvent {
-		match event.kind {
-			EventKind::Modify(ModifyKind::Data(_)) => {
-				max(self, CoalescedFsEvent::NewData)
-			}
+impl Drop for LogWatcher {
+	/// Forgets the read files when the watcher stops, which also happens when its task is aborted, because
+	/// their identities may be reused by other files once they are deleted.
+	fn drop(&mut self) {
+		if let Some(read_files) = &self.read_files {
+			read_files.remove(self.state.identity);
 			
-			EventKind::Create(CreateKind::File) => {
-				max(self, CoalescedFsEvent::NewFile)
+			if let Some(rotated_file) = &self.rotated_file {
+				read_files.remove(rotated_file.state.identity);
 			}
-			
-			_ => self
 		}
 	}
 }
 
 struct LogWatchingState {
 	path: PathBuf,
-	lines: Lines<BufReader<File>>,
+	reader: BufReader<File>,
+	identity: Option<FileIdentity>,
+	/// Number of bytes of complete lines read from the file.
+	position: u64,
+	/// Bytes read after the last complete line, whose line ending has not been written yet.
+	partial_line: Vec<u8>,
+	/// The first bytes of the file that were read, which are compared to the current first bytes of the file to detect
+	/// that it was truncated and written again.
+	head: Vec<u8>,
+	/// Size and modification time of the file when truncation was last checked.
+	last_metadata: Option<(u64, Option<SystemTime>)>,
+	/// When the first bytes of the file were last compared, or `None` if they have not been compared yet.
+	head_checked_at: Option<Instant>,
 	fs_watcher: Arc<FsWatcher>,
 }
 
 impl LogWatchingState {
 	const DEFAULT_BUFFER_CAPACITY: usize = 1024 * 4;
 	
+	/// Maximum number of bytes at the start of the file that are compared to detect truncation.
+	const HEAD_LENGTH: usize = 128;
+	
+	/// How often the first bytes are compared even if the file has only grown since the last check.
+	const HEAD_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+	
 	async fn initialize(path: PathBuf, fs_watcher: Arc<FsWatcher>) -> Result<LogWatchingState> {
 		fs_watcher.watch(&path).await.context("Could not create filesystem watcher")?;
 		
 		let file = File::open(&path).await.context("Could not open file")?;
-		let lines = BufReader::with_capacity(Self::DEFAULT_BUFFER_CAPACITY, file).lines();
+		let identity = file.metadata().await.ok().and_then(|metadata| FileIdentity::of(&metadata));
+		let reader = BufReader::with_capacity(Self::DEFAULT_BUFFER_CAPACITY, file);
 		
-		Ok(LogWatchingState { path, lines, fs_watcher })
+		Ok(LogWatchingState { path, reader, identity, position: 0, partial_line: Vec::new(), head: Vec::new(), last_metadata: None, head_checked_at: None, fs_watcher })
+	}
+	
+	/// Opens the file that currently exists at the same path.
+	async fn reopen(&self) -> Result<LogWatchingState> {
+		LogWatchingState::initialize(self.path.clone(), Arc::clone(&self.fs_watcher)).await
 	}
 	
-	async fn reinitialize(self) -> Result<LogWatchingState> {
-		LogWatchingState::initialize(self.path, self.fs_watcher).await
+	/// Reads the next line without its line ending, or returns `None` at the end of the file. A line without
+	/// a line ending at the end of the file is kept until the rest of it is written.
+	async fn next_line(&mut self) -> io::Result<Option<String>> {
+		let read_bytes = self.reader.read_until(b'\n', &mut self.partial_line).await?;
+		
+		if read_bytes == 0 || !self.partial_line.ends_with(b"\n") {
+			return Ok(None);
+		}
+		
+		let mut line = mem::take(&mut self.partial_line);
+		
+		// The first bytes are kept as they are read, unless reading did not start at the beginning of the file.
+		if self.position == self.head.len() as u64 && self.head.len() < Self::HEAD_LENGTH {
+			let head_bytes = &line[..line.len().min(Self::HEAD_LENGTH - self.head.len())];
+			self.head.extend_from_slice(head_bytes);
+		}
+		
+		self.position += line.len() as u64;
+		
+		line.pop();
+		if line.ends_with(b"\r") {
+			line.pop();
+		}
+		
+		String::from_utf8(line).map(Some).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
 	}
+	
+	/// Returns whether a file opened at the same path is the file that is being read. Without file identities,
+	/// the file is assumed to be the same if it is not smaller than the number of bytes read, and starts with the
+	/// same bytes.
+	async fn is_same_file(&self, other: &mut LogWatchingState) -> bool {
+		if let (Some(identity), Some(other_identity)) = (self.identity, other.identity) {
+			return identity == other_identity;
+		}
+		
+		if self.position == 0 {
+			return false;
+		}
+		
+		match other.reader.get_ref().metadata().await {
+			Ok(metadata) if metadata.len() >= self.position => other.starts_with(&self.head).await,
+			_ => false,
+		}
+	}
+	
+	/// Returns whether the file starts with the bytes, and moves back to the beginning of the file.
+	async fn starts_with(&mut self, bytes: &[u8]) -> bool {
+		let mut head = vec![0; bytes.len()];
+		let starts_with = self.reader.read_exact(&mut head).await.is_ok() && head == bytes;
+		self.seek(0).await.is_ok() && starts_with
+	}
+	
+	fn read_position(&self) -> Option<ReadPosition> {
+		self.identity.map(|identity| ReadPosition { identity, offset: self.position })
+	}
+	
+	/// Continues reading from a saved position, if it was saved for the same file and the file has not been
+	/// truncated since. Returns whether reading was resumed.
+	async fn resume(&mut self, saved_position: ReadPosition) -> bool {
+		if self.identity != Some(saved_position.identity) {
+			return false;
+		}
+		
+		match self.reader.get_ref().metadata().await {
+			Ok(metadata) if metadata.len() >= saved_position.offset => {}
+			_ => return false,
+		}
+		
+		if let Err(e) = self.seek(saved_position.offset).await {
+			println!("[LogWatcher] Could not seek in file \"{}\": {}", self.path.to_string_lossy(), e);
+			return false;
+		}
+		
+		true
+	}
+	
+	async fn seek(&mut self, offset: u64) -> io::Result<()> {
+		self.position = self.reader.seek(SeekFrom::Start(offset)).await?;
+		self.partial_line.clear();
+		self.head.clear();
+		Ok(())
+	}
+	
+	/// Returns whether the file was truncated in place, e.g. by `copytruncate` rotation. This is the case if it has
+	/// become smaller than the number of bytes read, or if it has grown past them again but starts with different bytes.
+	///
+	/// The first bytes are only compared if the size and modification time cannot be explained by appended lines,
+	/// or at least once per `HEAD_CHECK_INTERVAL`. Truncation is not detected if the file is written again with
+	/// the same first bytes, which are only compared on Unix. Lines written after truncation may then be skipped
+	/// until the file grows past the read position.
+	async fn is_truncated(&mut self) -> bool {
+		let metadata = match self.reader.get_ref().metadata().await {
+			Ok(metadata) => metadata,
+			Err(_) => return false,
+		};
+		
+		if metadata.len() < self.position + self.partial_line.len() as u64 {
+			return true;
+		}
+		
+		let current_metadata = (metadata.len(), metadata.modified().ok());
+		let is_appended = match self.last_metadata.replace(current_metadata) {
+			Some((last_length, last_modified)) => current_metadata.0 > last_length || current_metadata == (last_length, last_modified),
+			None => false,
+		};
+		
+		let is_check_due = self.head_checked_at.is_none_or(|checked_at| checked_at.elapsed() >= Self::HEAD_CHECK_INTERVAL);
+		if is_appended && !is_check_due {
+			return false;
+		}
+		
+		let head_length = self.position.min(Self::HEAD_LENGTH as u64) as usize;
+		if head_length == 0 {
+			return false;
+		}
+		
+		let Some(head) = read_head(self.reader.get_ref(), head_length).await else {
+			return false;
+		};
+		
+		self.head_checked_at = Some(Instant::now());
+		
+		let is_truncated = head[..self.head.len()] != self.head;
+		self.head = head;
+		is_truncated
+	}
+}
+
+/// Reads the first bytes of a file without moving its cursor.
+#[cfg(unix)]
+async fn read_head(file: &File, length: usize) -> Option<Vec<u8>> {
+	use std::os::unix::fs::FileExt;
+	
+	let file = file.try_clone().await.ok()?.into_std().await;
+	
+	tokio::task::spawn_blocking(move || {
+		let mut head = vec![0; length];
+		file.read_exact_at(&mut head, 0).ok().map(|_| head)
+	}).await.ok().flatten()
+}
+
+#[cfg(not(unix))]
+async fn read_head(_file: &File, _length: usize) -> Option<Vec<u8>> {
+	None
 }
 
 struct LogLineProcessor {
@@ -229,9 +741,9 @@ struct LogLineProcessor {
 }
 
 impl LogLineProcessor {
-	async fn process_lines(&self, reader: &mut Lines<BufReader<File>>) -> bool {
+	async fn process_lines(&self, state: &mut LogWatchingState) -> bool {
 		loop {
-			match reader.next_line().await {
+			match state.next_line().await {
 				Ok(maybe_line) => match maybe_line {
 					Some(line) => self.handle_line(line),
 					None => return true,
@@ -244,13 +756,421 @@ impl LogLineProcessor {
 		}
 	}
 	
+	fn count_rotation(&self) {
+		self.metrics.log_rotations_total.get_or_create(&self.metadata.get_label_set()).inc();
+	}
+	
 	fn handle_line(&self, line: String) {
-		let (kind, family) = match self.metadata.kind {
-			LogFileKind::Access => ("access log", &self.metrics.requests_total),
-			LogFileKind::Error => ("error log", &self.metrics.errors_total),
+		match &self.metadata.kind {
+			LogFileKind::Access(format) => self.handle_access_line(&line, format),
+			LogFileKind::Error => self.handle_error_line(&line),
+		}
+	}
+	
+	fn handle_access_line(&self, line: &str, format: &AccessLogFormat) {
+		match AccessLogLineParts::parse(line, format) {
+			Ok(parts) => {
+				println!("[LogWatcher] Received access log line from \"{}\": {}", self.metadata.label, parts);
+				
+				let file_labels = self.metadata.get_label_set();
+				let request_line = parts.request.map(RequestLineParts::parse);
+				
+				let (method, protocol) = match &request_line {
+					Some(Some(request_line)) => (request_line.method_label(), request_line.protocol_label()),
+					Some(None) => ("invalid", "invalid"),
+					None => ("", ""),
+				};
+				
+				if let Some(None) = request_line {
+					self.metrics.malformed_requests_total.get_or_create(&file_labels).inc();
+				}
+				
+				let request_labels = RequestLabels {
+					file: self.metadata.label.clone(),
+					status: parts.response_status.to_string(),
+					status_class: parts.response_status_class(),
+					method,
+					protocol,
+					pattern_labels: self.metadata.pattern_labels.clone(),
+				};
+				
+				self.metrics.requests_total.get_or_create(&request_labels).inc();
+				
+				if let (Some(routes), Some(request_line)) = (&self.metrics.routes, &request_line) {
+					let route = match request_line {
+						Some(request_line) => routes.rules.route(request_line.path),
+						None => String::from(FALLBACK_ROUTE),
+					};
+					
+					let route_request_labels = RouteRequestLabels {
+						file: self.metadata.label.clone(),
+						route: route.clone(),
+						method,
+						status_class: request_labels.status_class,
+						pattern_labels: self.metadata.pattern_labels.clone(),
+					};
+					
+					routes.requests_total.get_or_create(&route_request_labels).inc();
+					
+					if let Some(response_time_seconds) = parts.response_time_seconds {
+						routes.request_duration_seconds.get_or_create(&RouteLabels { file: self.metadata.label.clone(), route, pattern_labels: self.metadata.pattern_labels.clone() }).observe(response_time_seconds);
+					}
+				}
+				
+				if let Some(response_time_seconds) = parts.response_time_seconds {
+					self.metrics.request_duration_seconds.get_or_create(&file_labels).observe(response_time_seconds);
+				}
+				
+				if let Some(response_bytes) = parts.response_bytes {
+					self.metrics.response_bytes_total.get_or_create(&file_labels).inc_by(response_bytes);
+					
+					if let Some(response_size_bytes) = &self.metrics.response_size_bytes {
+						response_size_bytes.get_or_create(&file_labels).observe(response_bytes as f64);
+					}
+				}
+			}
+			Err(e) => {
+				println!("[LogWatcher] Could not parse access log line from \"{}\" ({}): {}", self.metadata.label, e.reason(), line);
+				self.metrics.access_log_parse_errors_total.get_or_create(&ParseErrorLabels { file: self.metadata.label.clone(), reason: e.reason(), pattern_labels: self.metadata.pattern_labels.clone() }).inc();
+			}
+		}
+	}
+	
+	fn handle_error_line(&self, line: &str) {
+		match ErrorLogLineParts::parse(line) {
+			Ok(parts) => {
+				println!("[LogWatcher] Received error log line from \"{}\": {}", self.metadata.label, parts);
+				
+				let error_labels = ErrorLabels {
+					file: self.metadata.label.clone(),
+					module: parts.module.to_string(),
+					level: parts.level.to_string(),
+					pattern_labels: self.metadata.pattern_labels.clone(),
+				};
+				
+				self.metrics.errors_total.get_or_create(&error_labels).inc();
+				
+				if let (Some(error_codes_total), Some(code)) = (&self.metrics.error_codes_total, parts.code) {
+					let error_code_labels = ErrorCodeLabels {
+						file: self.metadata.label.clone(),
+						code: self.metrics.error_code_label(code),
+						pattern_labels: self.metadata.pattern_labels.clone(),
+					};
+					
+					error_codes_total.get_or_create(&error_code_labels).inc();
+				}
+			}
+			Err(e) => {
+				println!("[LogWatcher] Could not parse error log line from \"{}\" ({}): {}", self.metadata.label, e.reason(), line);
+				self.metrics.error_log_parse_errors_total.get_or_create(&ParseErrorLabels { file: self.metadata.label.clone(), reason: e.reason(), pattern_labels: self.metadata.pattern_labels.clone() }).inc();
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::collections::{HashMap, HashSet};
+	use std::fs;
+	use std::io::Write;
+	use std::path::{Path, PathBuf};
+	use std::sync::Arc;
+	use std::time::Duration;
+	
+	use prometheus_client::encoding::text::encode;
+	use prometheus_client::registry::Registry;
+	
+	use crate::logs::{create_test_folder, LogFileSource, LogSource, WatcherBackend};
+	use crate::logs::filesystem_watcher::{FsEventCallbacks, FsWatcher};
+	use crate::logs::log_file_pattern::LogFilePath;
+	use crate::metrics::{Metrics, MetricsConfiguration};
+	
+	use super::{canonical_path, LogFileKind, LogFileMetadata, LogWatcherConfiguration, LogWatcherContext, LogWatchingState, ReadFiles, RunningLogWatchers, StartPosition, WatcherSettings};
+	
+	const ERROR_LINE: &str = "[Wed Oct 11 14:32:52 2023] [core:error] [pid 1] message\n";
+	const OTHER_ERROR_LINE: &str = "[Thu Oct 12 09:15:03 2023] [core:error] [pid 2] other message\n";
+	
+	fn log_file(path: &str) -> LogFilePath {
+		LogFilePath { path: PathBuf::from(path), label: String::new(), pattern_labels: Vec::new() }
+	}
+	
+	fn error_log_source(folder: &Path) -> LogFileSource {
+		LogFileSource::parse(LogSource { pattern: format!("{}/*.error.log", folder.display()), label: None, kind: LogFileKind::Error, start_position: StartPosition::End }).unwrap()
+	}
+	
+	fn append(path: &Path, text: &str) {
+		fs::OpenOptions::new().append(true).open(path).unwrap().write_all(text.as_bytes()).unwrap();
+	}
+	
+	fn create_fs_watcher(fs_callbacks: FsEventCallbacks) -> Arc<FsWatcher> {
+		Arc::new(FsWatcher::new(fs_callbacks, WatcherBackend::Polling, Duration::from_millis(10)).unwrap())
+	}
+	
+	/// Creates log watchers that poll for changes, without any watched files.
+	fn create_watchers(rotation_grace_period: Option<Duration>) -> (Registry, RunningLogWatchers) {
+		let (registry, metrics) = Metrics::new(MetricsConfiguration::default());
+		let fs_callbacks = FsEventCallbacks::new();
+		let fs_watcher = create_fs_watcher(fs_callbacks.clone());
+		let context = LogWatcherContext { metrics, fs_watcher, read_positions: None, rotation_grace_period, read_files: Some(ReadFiles::default()) };
+		(registry, RunningLogWatchers { context, fs_callbacks, files: HashMap::new(), watched_folders: HashSet::new() })
+	}
+	
+	async fn start_error_log(watchers: &mut RunningLogWatchers, path: &Path, start_position: StartPosition) {
+		let (path, metadata) = LogFileMetadata::new(log_file(&path.to_string_lossy()), LogFileKind::Error);
+		watchers.start_file(canonical_path(&path), path, metadata, start_position, None).await.unwrap();
+	}
+	
+	/// Returns the value of the first series whose text starts with the prefix.
+	fn metric_value(registry: &Registry, prefix: &str) -> Option<f64> {
+		let mut text = String::new();
+		encode(&mut text, registry).unwrap();
+		text.lines().find(|line| line.starts_with(prefix)).and_then(|line| line.rsplit(' ').next()).and_then(|value| value.parse().ok())
+	}
+	
+	async fn wait_for_metric(registry: &Registry, prefix: &str, expected_value: f64) {
+		for _ in 0..500 {
+			if metric_value(registry, prefix) == Some(expected_value) {
+				return;
+			}
+			tokio::time::sleep(Duration::from_millis(10)).await;
+		}
+		
+		panic!("Metric {} is {:?} instead of {}", prefix, metric_value(registry, prefix), expected_value);
+	}
+	
+	#[test]
+	fn duplicate_files() {
+		let mut watcher = LogWatcherConfiguration::new(WatcherSettings::default());
+		assert!(watcher.add_file(log_file("/nonexistent/a.log"), LogFileKind::Error, StartPosition::End).is_ok());
+		assert!(watcher.add_file(log_file("/nonexistent/b.log"), LogFileKind::Error, StartPosition::End).is_ok());
+		assert!(matches!(watcher.add_file(log_file("/nonexistent/a.log"), LogFileKind::Error, StartPosition::End), Err(err) if err.to_string() == "File is matched more than once: /nonexistent/a.log"));
+		assert_eq!(watcher.files.len(), 2);
+	}
+	
+	#[tokio::test]
+	async fn new_and_deleted_files() {
+		let folder = create_test_folder("rescan");
+		fs::write(folder.join("a.error.log"), ERROR_LINE).unwrap();
+		
+		let (registry, mut watchers) = create_watchers(None);
+		let sources = Arc::from(vec![error_log_source(&folder)]);
+		
+		watchers.rescan(&sources).await;
+		wait_for_metric(&registry, "apache_errors_total{file=\"a\"", 1.0).await;
+		
+		fs::write(folder.join("b.error.log"), ERROR_LINE.repeat(2)).unwrap();
+		watchers.rescan(&sources).await;
+		wait_for_metric(&registry, "apache_errors_total{file=\"b\"", 2.0).await;
+		
+		// Files are retired when they are missing in two consecutive searches.
+		fs::remove_file(folder.join("a.error.log")).unwrap();
+		watchers.rescan(&sources).await;
+		assert_eq!(metric_value(&registry, "apache_errors_total{file=\"a\""), Some(1.0));
+		
+		watchers.rescan(&sources).await;
+		assert_eq!(metric_value(&registry, "apache_errors_total{file=\"a\""), None);
+		assert_eq!(metric_value(&registry, "apache_errors_total{file=\"b\""), Some(2.0));
+		assert_eq!(watchers.files.len(), 1);
+		assert_eq!(watchers.watched_folders.len(), 1);
+		
+		fs::remove_dir_all(&folder).unwrap();
+	}
+	
+	#[tokio::test]
+	async fn rotated_file_found_by_rescan() {
+		let folder = create_test_folder("rescan_rotated");
+		fs::write(folder.join("a.error.log"), ERROR_LINE).unwrap();
+		
+		let (registry, mut watchers) = create_watchers(None);
+		let sources = Arc::from(vec![error_log_source(&folder)]);
+		
+		watchers.rescan(&sources).await;
+		wait_for_metric(&registry, "apache_errors_total{file=\"a\"", 1.0).await;
+		
+		fs::rename(folder.join("a.error.log"), folder.join("a-1.error.log")).unwrap();
+		fs::write(folder.join("a.error.log"), ERROR_LINE).unwrap();
+		wait_for_metric(&registry, "apache_log_rotations_total{file=\"a\"", 1.0).await;
+		
+		// The renamed file continues where it was read up to under its old name.
+		watchers.rescan(&sources).await;
+		assert_eq!(watchers.files.len(), 2);
+		
+		append(&folder.join("a-1.error.log"), ERROR_LINE);
+		wait_for_metric(&registry, "apache_errors_total{file=\"a-1\"", 1.0).await;
+		wait_for_metric(&registry, "apache_errors_total{file=\"a\"", 2.0).await;
+		
+		fs::remove_dir_all(&folder).unwrap();
+	}
+	
+	#[tokio::test]
+	async fn line_written_in_two_parts() {
+		let folder = create_test_folder("partial_line");
+		let path = folder.join("a.error.log");
+		fs::write(&path, "").unwrap();
+		
+		let (registry, mut watchers) = create_watchers(None);
+		start_error_log(&mut watchers, &path, StartPosition::Beginning).await;
+		
+		// Neither part can be parsed on its own.
+		let (first_part, second_part) = ERROR_LINE.split_at(ERROR_LINE.find("core").unwrap());
+		append(&path, first_part);
+		tokio::time::sleep(Duration::from_millis(100)).await;
+		assert_eq!(metric_value(&registry, "apache_errors_total{file=\"\""), None);
+		
+		append(&path, second_part);
+		wait_for_metric(&registry, "apache_errors_total{file=\"\",module=\"core\"", 1.0).await;
+		
+		fs::remove_dir_all(&folder).unwrap();
+	}
+	
+	#[tokio::test]
+	async fn truncated_file() {
+		let folder = create_test_folder("truncated");
+		let path = folder.join("a.error.log");
+		fs::write(&path, ERROR_LINE.repeat(2)).unwrap();
+		
+		let (registry, mut watchers) = create_watchers(None);
+		start_error_log(&mut watchers, &path, StartPosition::Beginning).await;
+		wait_for_metric(&registry, "apache_errors_total{file=\"\"", 2.0).await;
+		
+		fs::write(&path, ERROR_LINE).unwrap();
+		wait_for_metric(&registry, "apache_log_rotations_total{file=\"\"", 1.0).await;
+		wait_for_metric(&registry, "apache_errors_total{file=\"\"", 3.0).await;
+		
+		fs::write(&path, OTHER_ERROR_LINE.repeat(3)).unwrap();
+		wait_for_metric(&registry, "apache_log_rotations_total{file=\"\"", 2.0).await;
+		wait_for_metric(&registry, "apache_errors_total{file=\"\"", 6.0).await;
+		
+		fs::remove_dir_all(&folder).unwrap();
+	}
+	
+	#[tokio::test]
+	#[cfg(unix)]
+	async fn truncated_file_written_past_read_position() {
+		let folder = create_test_folder("truncated_written");
+		let path = folder.join("a.error.log");
+		fs::write(&path, ERROR_LINE.repeat(2)).unwrap();
+		
+		let mut state = LogWatchingState::initialize(path.clone(), create_fs_watcher(FsEventCallbacks::new())).await.unwrap();
+		while state.next_line().await.unwrap().is_some() {}
+		assert!(!state.is_truncated().await);
+		
+		// The first bytes are not compared again if lines are only appended.
+		let head_checked_at = state.head_checked_at;
+		append(&path, ERROR_LINE);
+		assert!(!state.is_truncated().await);
+		assert_eq!(state.head_checked_at, head_checked_at);
+		
+		// A file that grew past the read position is only compared once the check interval has passed.
+		fs::write(&path, OTHER_ERROR_LINE.repeat(4)).unwrap();
+		state.head_checked_at = None;
+		assert!(state.is_truncated().await);
+		
+		state.seek(0).await.unwrap();
+		assert_eq!(state.next_line().await.unwrap().as_deref(), Some(OTHER_ERROR_LINE.trim_end()));
+		assert!(!state.is_truncated().await);
+		
+		fs::remove_dir_all(&folder).unwrap();
+	}
+	
+	#[tokio::test]
+	async fn rotated_file_is_read_during_grace_period() {
+		let folder = create_test_folder("grace_period");
+		let path = folder.join("a.error.log");
+		fs::write(&path, ERROR_LINE).unwrap();
+		
+		let (registry, mut watchers) = create_watchers(Some(Duration::from_secs(60)));
+		start_error_log(&mut watchers, &path, StartPosition::Beginning).await;
+		wait_for_metric(&registry, "apache_errors_total{file=\"\"", 1.0).await;
+		
+		fs::rename(&path, folder.join("a.error.log.1")).unwrap();
+		fs::write(&path, ERROR_LINE).unwrap();
+		wait_for_metric(&registry, "apache_log_rotations_total{file=\"\"", 1.0).await;
+		wait_for_metric(&registry, "apache_errors_total{file=\"\"", 2.0).await;
+		
+		append(&folder.join("a.error.log.1"), &ERROR_LINE.repeat(2));
+		wait_for_metric(&registry, "apache_errors_total{file=\"\"", 4.0).await;
+		
+		append(&path, ERROR_LINE);
+		wait_for_metric(&registry, "apache_errors_total{file=\"\"", 5.0).await;
+		
+		fs::remove_dir_all(&folder).unwrap();
+	}
+	
+	#[tokio::test]
+	async fn same_file_without_identity() {
+		let folder = create_test_folder("same_file");
+		let path = folder.join("a.error.log");
+		fs::write(&path, ERROR_LINE.repeat(2)).unwrap();
+		
+		let fs_watcher = create_fs_watcher(FsEventCallbacks::new());
+		let open_without_identity = || async {
+			let mut state = LogWatchingState::initialize(path.clone(), Arc::clone(&fs_watcher)).await.unwrap();
+			state.identity = None;
+			state
 		};
 		
-		println!("[LogWatcher] Received {} line from \"{}\": {}", kind, self.metadata.label, line);
-		family.get_or_create(&self.metadata.get_label_set()).inc();
+		let mut state = open_without_identity().await;
+		assert!(!state.is_same_file(&mut open_without_identity().await).await);
+		
+		while state.next_line().await.unwrap().is_some() {}
+		
+		let mut same_state = open_without_identity().await;
+		assert!(state.is_same_file(&mut same_state).await);
+		assert_eq!(same_state.next_line().await.unwrap().as_deref(), Some(ERROR_LINE.trim_end()));
+		
+		fs::write(&path, ERROR_LINE).unwrap();
+		assert!(!state.is_same_file(&mut open_without_identity().await).await);
+		
+		fs::write(&path, OTHER_ERROR_LINE.repeat(2)).unwrap();
+		assert!(!state.is_same_file(&mut open_without_identity().await).await);
+		
+		fs::remove_dir_all(&folder).unwrap();
+	}
+	
+	#[tokio::test]
+	#[cfg(unix)]
+	async fn resume_from_saved_position() {
+		use crate::logs::read_positions::{FileIdentity, ReadPosition};
+		
+		let folder = create_test_folder("resume");
+		let path = folder.join("a.error.log");
+		fs::write(&path, format!("{}{}", ERROR_LINE, OTHER_ERROR_LINE)).unwrap();
+		
+		let fs_watcher = create_fs_watcher(FsEventCallbacks::new());
+		let mut state = LogWatchingState::initialize(path.clone(), Arc::clone(&fs_watcher)).await.unwrap();
+		let identity = state.identity.unwrap();
+		let offset = ERROR_LINE.len() as u64;
+		
+		assert!(state.resume(ReadPosition { identity, offset }).await);
+		assert_eq!(state.next_line().await.unwrap().as_deref(), Some(OTHER_ERROR_LINE.trim_end()));
+		
+		// A position saved for a different file is ignored.
+		let mut state = LogWatchingState::initialize(path.clone(), Arc::clone(&fs_watcher)).await.unwrap();
+		assert!(!state.resume(ReadPosition { identity: FileIdentity { device: identity.device, inode: identity.inode + 1 }, offset }).await);
+		assert_eq!(state.next_line().await.unwrap().as_deref(), Some(ERROR_LINE.trim_end()));
+		
+		// A position past the end of the file is ignored, because the file was truncated since it was saved.
+		let mut state = LogWatchingState::initialize(path.clone(), Arc::clone(&fs_watcher)).await.unwrap();
+		assert!(!state.resume(ReadPosition { identity, offset: 1000 }).await);
+		assert_eq!(state.next_line().await.unwrap().as_deref(), Some(ERROR_LINE.trim_end()));
+		
+		fs::remove_dir_all(&folder).unwrap();
+	}
+	
+	#[tokio::test]
+	async fn read_existing_lines_from_beginning() {
+		let folder = create_test_folder("beginning");
+		let path = folder.join("a.error.log");
+		fs::write(&path, format!("{}{}", ERROR_LINE, OTHER_ERROR_LINE)).unwrap();
+		
+		let (registry, mut watchers) = create_watchers(None);
+		start_error_log(&mut watchers, &path, StartPosition::Beginning).await;
+		wait_for_metric(&registry, "apache_errors_total{file=\"\",module=\"core\",level=\"error\"}", 2.0).await;
+		
+		append(&path, OTHER_ERROR_LINE);
+		wait_for_metric(&registry, "apache_errors_total{file=\"\",module=\"core\",level=\"error\"}", 3.0).await;
+		
+		fs::remove_dir_all(&folder).unwrap();
 	}
 }
@@ -1,48 +1,135 @@
-use std::env;
-use std::env::VarError;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
 
-use anyhow::{anyhow, bail, Context, Result};
+use anyhow::{bail, Context, Result};
 
-use log_file_watcher::{LogFileKind, LogWatcherConfiguration};
+pub use filesystem_watcher::WatcherBackend;
+pub use log_file_pattern::REGEX_PATTERN_PREFIX;
+pub use log_file_watcher::{LogFileKind, StartPosition};
+use log_file_watcher::LogWatcherConfiguration;
 
-use crate::logs::log_file_pattern::{LogFilePath, parse_log_file_pattern_from_str};
+use crate::logs::log_file_pattern::{LogFilePath, LogFilePattern, parse_log_file_pattern_from_str};
+use crate::logs::read_positions::ReadPositions;
 use crate::metrics::Metrics;
 
+pub mod access_log_format;
 mod access_log_parser;
+pub mod error_log_parser;
 mod filesystem_watcher;
 mod log_file_pattern;
 mod log_file_watcher;
+mod read_positions;
+mod request_line_parser;
 
-pub fn find_log_files(environment_variable_name: &str, log_kind: &str) -> Result<Vec<LogFilePath>> {
-	let log_file_pattern_str = env::var(environment_variable_name).map_err(|err| match err {
-		VarError::NotPresent => anyhow!("Environment variable {} must be set", environment_variable_name),
-		VarError::NotUnicode(_) => anyhow!("Environment variable {} contains invalid characters", environment_variable_name)
-	})?;
-	
-	let log_file_pattern = parse_log_file_pattern_from_str(&log_file_pattern_str).with_context(|| format!("Could not parse pattern: {}", log_file_pattern_str))?;
-	let log_files = log_file_pattern.search().with_context(|| format!("Could not search files: {}", log_file_pattern_str))?;
-	
-	if log_files.is_empty() {
-		bail!("No files match pattern: {}", log_file_pattern_str);
+/// Default interval between searches for new and deleted log files.
+pub const DEFAULT_RESCAN_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Default time for which a log file that was renamed by rotation is still read.
+pub const DEFAULT_ROTATION_GRACE_PERIOD: Duration = Duration::from_secs(10);
+
+/// Default interval between checks of watched files when polling.
+pub const DEFAULT_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Interval between saves of read positions to the state file.
+pub const STATE_CHECKPOINT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// A pattern of log files of the same kind.
+pub struct LogSource {
+	pub pattern: String,
+	/// Overrides the `file` label of all files matched by the pattern.
+	pub label: Option<String>,
+	pub kind: LogFileKind,
+	pub start_position: StartPosition,
+}
+
+/// Settings that apply to all watched log files.
+pub struct WatcherSettings {
+	/// How often to search for new and deleted log files, or `None` to only search at startup.
+	pub rescan_interval: Option<Duration>,
+	/// File that stores read positions, so that reading resumes where it left off after a restart.
+	pub state_file: Option<PathBuf>,
+	/// How long to keep reading a log file after it was renamed by rotation, or `None` to only read it once.
+	pub rotation_grace_period: Option<Duration>,
+	pub backend: WatcherBackend,
+	/// How often to check watched files when polling.
+	pub poll_interval: Duration,
+}
+
+impl Default for WatcherSettings {
+	fn default() -> Self {
+		WatcherSettings {
+			rescan_interval: Some(DEFAULT_RESCAN_INTERVAL),
+			state_file: None,
+			rotation_grace_period: Some(DEFAULT_ROTATION_GRACE_PERIOD),
+			backend: WatcherBackend::Auto,
+			poll_interval: DEFAULT_POLL_INTERVAL,
+		}
+	}
+}
+
+/// A [LogSource] with a parsed pattern, which can be searched repeatedly.
+struct LogFileSource {
+	source: LogSource,
+	pattern: LogFilePattern,
+}
+
+impl LogFileSource {
+	fn parse(source: LogSource) -> Result<Self> {
+		let pattern = parse_log_file_pattern_from_str(&source.pattern).with_context(|| format!("Could not parse {} pattern: {}", source.kind.name(), source.pattern))?;
+		Ok(LogFileSource { source, pattern })
 	}
 	
-	for log_file in &log_files {
-		println!("Found {} file: {} (label \"{}\")", log_kind, log_file.path.display(), log_file.label);
+	fn kind(&self) -> &LogFileKind {
+		&self.source.kind
 	}
 	
-	Ok(log_files)
+	fn search(&self) -> Result<Vec<LogFilePath>> {
+		let mut log_files = self.pattern.search().with_context(|| format!("Could not search {} files: {}", self.source.kind.name(), self.source.pattern))?;
+		
+		if let Some(label) = &self.source.label {
+			for log_file in &mut log_files {
+				log_file.label = label.clone();
+			}
+		}
+		
+		Ok(log_files)
+	}
 }
 
-pub async fn start_log_watcher(access_log_files: Vec<LogFilePath>, error_log_files: Vec<LogFilePath>, metrics: Metrics) -> Result<()> {
-	let mut watcher = LogWatcherConfiguration::new();
+pub fn find_log_files(sources: Vec<LogSource>, settings: WatcherSettings) -> Result<LogWatcherConfiguration> {
+	let mut watcher = LogWatcherConfiguration::new(settings);
 	
-	for log_file in access_log_files.into_iter() {
-		watcher.add_file(log_file, LogFileKind::Access);
-	}
-	
-	for log_file in error_log_files.into_iter() {
-		watcher.add_file(log_file, LogFileKind::Error);
+	for source in sources {
+		let source = LogFileSource::parse(source)?;
+		let log_kind = source.kind().name();
+		let log_files = source.search()?;
+		
+		if log_files.is_empty() {
+			bail!("No {} files match pattern: {}", log_kind, source.source.pattern);
+		}
+		
+		for log_file in log_files {
+			let pattern_labels = log_file.pattern_labels.iter().map(|(name, value)| format!(", {} \"{}\"", name, value)).collect::<String>();
+			println!("Found {} file: {} (label \"{}\"{})", log_kind, log_file.path.display(), log_file.label, pattern_labels);
+			watcher.add_file(log_file, source.kind().clone(), source.source.start_position).with_context(|| format!("Could not add {} file matched by pattern: {}", log_kind, source.source.pattern))?;
+		}
+		
+		watcher.add_source(source);
 	}
 	
+	Ok(watcher)
+}
+
+pub async fn start_log_watcher(watcher: LogWatcherConfiguration, metrics: Metrics) -> Result<Option<Arc<ReadPositions>>> {
 	watcher.start(&metrics).await
 }
+
+/// Creates an empty folder for test files, which is unique to the test and the test process.
+#[cfg(test)]
+fn create_test_folder(name: &str) -> PathBuf {
+	let folder = std::env::temp_dir().join(format!("apache_prometheus_exporter_{}_{}", name, std::process::id()));
+	let _ = std::fs::remove_dir_all(&folder);
+	std::fs::create_dir_all(&folder).unwrap();
+	folder
+}
@@ -0,0 +1,169 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+/// Identifies a file independently of its path, so that the position in a rotated file is not applied to the file
+/// that replaced it.
+#[derive(Debug, Copy, Clone, Eq, Hash, PartialEq)]
+pub struct FileIdentity {
+	pub device: u64,
+	pub inode: u64,
+}
+
+impl FileIdentity {
+	#[cfg(unix)]
+	pub fn of(metadata: &fs::Metadata) -> Option<FileIdentity> {
+		use std::os::unix::fs::MetadataExt;
+		Some(FileIdentity { device: metadata.dev(), inode: metadata.ino() })
+	}
+	
+	#[cfg(not(unix))]
+	pub fn of(_metadata: &fs::Metadata) -> Option<FileIdentity> {
+		None
+	}
+}
+
+/// The number of bytes that have been read from a file.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct ReadPosition {
+	pub identity: FileIdentity,
+	pub offset: u64,
+}
+
+/// Read positions of watched log files, which are saved to a state file so that reading can resume where it
+/// left off after the exporter restarts.
+pub struct ReadPositions {
+	path: PathBuf,
+	/// Positions loaded from the state file when the exporter started.
+	saved: HashMap<PathBuf, ReadPosition>,
+	/// Positions of the files that are currently watched, which will be written to the state file.
+	current: Mutex<HashMap<PathBuf, ReadPosition>>,
+}
+
+impl ReadPositions {
+	/// Loads read positions from the state file. A missing state file is treated as empty, because it is created
+	/// by the first checkpoint.
+	pub fn load(path: &Path) -> Result<ReadPositions> {
+		let saved = match fs::read_to_string(path) {
+			Ok(contents) => parse_state_file(&contents).with_context(|| format!("Invalid state file: {}", path.display()))?,
+			Err(e) if e.kind() == ErrorKind::NotFound => HashMap::new(),
+			Err(e) => return Err(e).with_context(|| format!("Could not read state file: {}", path.display())),
+		};
+		
+		Ok(ReadPositions { path: path.to_path_buf(), saved, current: Mutex::new(HashMap::new()) })
+	}
+	
+	/// Returns the position that was saved for a file before the exporter started.
+	pub fn saved_position(&self, path: &Path) -> Option<ReadPosition> {
+		self.saved.get(path).copied()
+	}
+	
+	pub fn update(&self, path: &Path, position: ReadPosition) {
+		self.current.lock().unwrap_or_else(PoisonError::into_inner).insert(path.to_path_buf(), position);
+	}
+	
+	pub fn remove(&self, path: &Path) {
+		self.current.lock().unwrap_or_else(PoisonError::into_inner).remove(path);
+	}
+	
+	/// Writes the current positions to the state file. The file is replaced atomically, so that it is never left
+	/// partially written if the exporter is killed during a checkpoint.
+	pub fn save(&self) -> Result<()> {
+		let contents = format_state_file(&self.current.lock().unwrap_or_else(PoisonError::into_inner))?;
+		
+		let mut temporary_path = self.path.clone().into_os_string();
+		temporary_path.push(".tmp");
+		
+		fs::write(&temporary_path, contents).with_context(|| format!("Could not write state file: {}", temporary_path.to_string_lossy()))?;
+		fs::rename(&temporary_path, &self.path).with_context(|| format!("Could not replace state file: {}", self.path.display()))
+	}
+	
+	pub async fn save_periodically(self: Arc<Self>, checkpoint_interval: Duration) {
+		let mut interval = tokio::time::interval(checkpoint_interval);
+		interval.tick().await;
+		
+		loop {
+			interval.tick().await;
+			
+			// Writing the file blocks, so it must not delay the other tasks on the runtime thread.
+			let read_positions = Arc::clone(&self);
+			match tokio::task::spawn_blocking(move || read_positions.save()).await {
+				Ok(Ok(())) => {}
+				Ok(Err(e)) => println!("[LogWatcher] {:#}", e),
+				Err(e) => println!("[LogWatcher] Could not save read positions: {}", e),
+			}
+		}
+	}
+}
+
+fn parse_state_file(contents: &str) -> Result<HashMap<PathBuf, ReadPosition>> {
+	let file = toml::from_str::<StateFile>(contents)?;
+	
+	Ok(file.files.into_iter().map(|file| {
+		let position = ReadPosition { identity: FileIdentity { device: file.device, inode: file.inode }, offset: file.offset };
+		(file.path, position)
+	}).collect())
+}
+
+fn format_state_file(positions: &HashMap<PathBuf, ReadPosition>) -> Result<String> {
+	let mut files = positions.iter().map(|(path, position)| FileSection {
+		path: path.clone(),
+		device: position.identity.device,
+		inode: position.identity.inode,
+		offset: position.offset,
+	}).collect::<Vec<_>>();
+	
+	files.sort_by(|a, b| a.path.cmp(&b.path));
+	
+	Ok(toml::to_string(&StateFile { files })?)
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+struct StateFile {
+	#[serde(default)]
+	files: Vec<FileSection>,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+struct FileSection {
+	path: PathBuf,
+	device: u64,
+	inode: u64,
+	offset: u64,
+}
+
+#[cfg(test)]
+mod tests {
+	use std::collections::HashMap;
+	use std::path::PathBuf;
+	
+	use super::{FileIdentity, format_state_file, parse_state_file, ReadPosition};
+	
+	#[test]
+	fn state_file_round_trip() {
+		let mut positions = HashMap::new();
+		positions.insert(PathBuf::from("/var/log/apache2/a.access.log"), ReadPosition { identity: FileIdentity { device: 2049, inode: 131 }, offset: 5120 });
+		positions.insert(PathBuf::from("/var/log/apache2/a.error.log"), ReadPosition { identity: FileIdentity { device: 2049, inode: 132 }, offset: 0 });
+		
+		let contents = format_state_file(&positions).unwrap();
+		assert_eq!(parse_state_file(&contents).unwrap(), positions);
+	}
+	
+	#[test]
+	fn empty_state_file() {
+		assert!(parse_state_file("").unwrap().is_empty());
+	}
+	
+	#[test]
+	fn invalid_state_file() {
+		assert!(parse_state_file("[[files]]\npath = \"/var/log/apache2/a.access.log\"\noffset = 10").is_err());
+	}
+}
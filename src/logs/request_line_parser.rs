@@ -0,0 +1,84 @@
+const KNOWN_METHODS: [&str; 9] = ["GET", "HEAD", "POST", "PUT", "DELETE", "CONNECT", "OPTIONS", "TRACE", "PATCH"];
+const KNOWN_PROTOCOLS: [&str; 5] = ["HTTP/1.0", "HTTP/1.1", "HTTP/2", "HTTP/2.0", "HTTP/3"];
+
+/// Parts of a request line (`%r`), such as `GET /index.html?page=1 HTTP/1.1`.
+pub struct RequestLineParts<'a> {
+	pub method: &'a str,
+	/// The request target without the query string.
+	pub path: &'a str,
+	pub protocol: &'a str,
+}
+
+impl<'a> RequestLineParts<'a> {
+	/// Returns `None` if the request line is malformed, which happens when Apache did not receive a request line
+	/// (logged as `-`), or when a client sent something that is not HTTP.
+	pub fn parse(request: &'a str) -> Option<RequestLineParts<'a>> {
+		let (method, request) = request.split_once(' ')?;
+		let (target, protocol) = request.rsplit_once(' ')?;
+		
+		if method.is_empty() || !method.bytes().all(|b| b.is_ascii_uppercase() || b == b'-' || b == b'_') {
+			return None;
+		}
+		
+		if target.is_empty() || target.contains(' ') || !protocol.starts_with("HTTP/") {
+			return None;
+		}
+		
+		let path = target.split_once('?').map_or(target, |(path, _)| path);
+		
+		Some(RequestLineParts { method, path, protocol })
+	}
+	
+	/// Returns the method, or `other` for non-standard methods to keep the number of label values bounded.
+	pub fn method_label(&self) -> &'static str {
+		KNOWN_METHODS.iter().find(|method| **method == self.method).unwrap_or(&"other")
+	}
+	
+	/// Returns the protocol, or `other` for unknown protocols to keep the number of label values bounded.
+	pub fn protocol_label(&self) -> &'static str {
+		KNOWN_PROTOCOLS.iter().find(|protocol| **protocol == self.protocol).unwrap_or(&"other")
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::RequestLineParts;
+	
+	#[test]
+	fn valid_request_line() {
+		let parts = RequestLineParts::parse("GET /index.html HTTP/1.1").unwrap();
+		assert_eq!(parts.method, "GET");
+		assert_eq!(parts.path, "/index.html");
+		assert_eq!(parts.protocol, "HTTP/1.1");
+	}
+	
+	#[test]
+	fn valid_request_line_with_query() {
+		let parts = RequestLineParts::parse("POST /search?q=a?b&page=2 HTTP/2.0").unwrap();
+		assert_eq!(parts.method, "POST");
+		assert_eq!(parts.path, "/search");
+		assert_eq!(parts.protocol, "HTTP/2.0");
+	}
+	
+	#[test]
+	fn labels() {
+		let parts = RequestLineParts::parse("PROPFIND /dav HTTP/1.1").unwrap();
+		assert_eq!(parts.method_label(), "other");
+		assert_eq!(parts.protocol_label(), "HTTP/1.1");
+		
+		let parts = RequestLineParts::parse("GET / HTTP/9.9").unwrap();
+		assert_eq!(parts.method_label(), "GET");
+		assert_eq!(parts.protocol_label(), "other");
+	}
+	
+	#[test]
+	fn malformed_request_lines() {
+		assert!(RequestLineParts::parse("-").is_none());
+		assert!(RequestLineParts::parse("").is_none());
+		assert!(RequestLineParts::parse("GET /").is_none());
+		assert!(RequestLineParts::parse("GET / FTP/1.0").is_none());
+		assert!(RequestLineParts::parse("GET /a b HTTP/1.1").is_none());
+		assert!(RequestLineParts::parse("get / HTTP/1.1").is_none());
+		assert!(RequestLineParts::parse("\\x16\\x03\\x01\\x02").is_none());
+	}
+}
@@ -1,38 +1,98 @@
-use std::env;
-use std::net::{IpAddr, SocketAddr};
-use std::str::FromStr;
-use std::sync::Mutex;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
 
-use anyhow::{anyhow, Context};
+use anyhow::Context;
+use clap::{Parser, Subcommand};
 use tokio::signal;
 
+use crate::config::Configuration;
 use crate::metrics::Metrics;
 use crate::web::WebServer;
 
+mod config;
 mod logs;
 mod metrics;
+mod route_rules;
 mod web;
 
-const ACCESS_LOG_FILE_PATTERN: &str = "ACCESS_LOG_FILE_PATTERN";
-const ERROR_LOG_FILE_PATTERN: &str = "ERROR_LOG_FILE_PATTERN";
+/// Exports Prometheus metrics from Apache access and error logs.
+#[derive(Parser)]
+#[command(version)]
+struct Cli {
+	/// Path to a TOML configuration file. If omitted, the configuration is read from environment variables.
+	#[arg(long, global = true, value_name = "PATH")]
+	config: Option<PathBuf>,
+	
+	#[command(subcommand)]
+	command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+	/// Watches the log files and serves metrics (default).
+	Serve,
+	/// Validates the configuration, lists the matched log files, and exits.
+	CheckConfig,
+}
 
 #[tokio::main(flavor = "current_thread")]
 async fn main() -> anyhow::Result<()> {
-	let host = env::var("HTTP_HOST").unwrap_or(String::from("127.0.0.1"));
-	let bind_ip = IpAddr::from_str(&host).map_err(|_| anyhow!("Invalid HTTP host: {}", host))?;
+	let cli = Cli::parse();
+	let config = load_configuration(cli.config.as_deref())?;
 	
+	match cli.command.unwrap_or(Command::Serve) {
+		Command::Serve => serve(config).await,
+		Command::CheckConfig => check_config(config),
+	}
+}
+
+fn load_configuration(path: Option<&Path>) -> anyhow::Result<Configuration> {
+	match path {
+		Some(path) => Configuration::from_file(path),
+		None => Configuration::from_env(),
+	}
+}
+
+async fn serve(config: Configuration) -> anyhow::Result<()> {
 	println!("Initializing exporter...");
 	
-	let access_log_files = logs::find_log_files(ACCESS_LOG_FILE_PATTERN, "access log").context("Could not find access log files")?;
-	let error_log_files = logs::find_log_files(ERROR_LOG_FILE_PATTERN, "error log").context("Could not find error log files")?;
+	let log_watcher = logs::find_log_files(config.log_sources, config.watcher).context("Could not find log files")?;
+	
+	let servers = config.listen_addresses.iter().map(|address| WebServer::try_bind(*address, &config.metrics_path)).collect::<anyhow::Result<Vec<_>>>().context("Could not configure web server")?;
+	let (metrics_registry, metrics) = Metrics::new(config.metrics);
+	metrics.start_expiring_stale_series();
 	
-	let server = WebServer::try_bind(SocketAddr::new(bind_ip, 9240)).context("Could not configure web server")?;
-	let (metrics_registry, metrics) = Metrics::new();
+	let read_positions = logs::start_log_watcher(log_watcher, metrics).await.context("Could not start watching logs")?;
 	
-	logs::start_log_watcher(access_log_files, error_log_files, metrics).await.context("Could not start watching logs")?;
-	tokio::spawn(server.serve(Mutex::new(metrics_registry)));
+	let metrics_registry = Arc::new(Mutex::new(metrics_registry));
+	for server in servers {
+		tokio::spawn(server.serve(Arc::clone(&metrics_registry)));
+	}
 	
 	signal::ctrl_c().await.with_context(|| "Could not register CTRL-C handler")?;
 	println!("Received CTRL-C, shutting down...");
+	
+	if let Some(read_positions) = read_positions {
+		read_positions.save().context("Could not save read positions")?;
+	}
+	
+	Ok(())
+}
+
+fn check_config(config: Configuration) -> anyhow::Result<()> {
+	logs::find_log_files(config.log_sources, config.watcher).context("Could not find log files")?;
+	println!("Configuration is valid.");
 	Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+	use clap::CommandFactory;
+	
+	use super::Cli;
+	
+	#[test]
+	fn verify_cli() {
+		Cli::command().debug_assert();
+	}
+}
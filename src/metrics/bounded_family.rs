@@ -0,0 +1,201 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::ops::Deref;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+use prometheus_client::metrics::counter::Counter;
+use prometheus_client::metrics::family::{Family, MetricConstructor};
+
+use crate::metrics::FileLabels;
+
+/// Label value that replaces labels derived from log content once a metric reaches its series limit.
+pub const OVERFLOW_LABEL_VALUE: &str = "__overflow__";
+
+/// Label set that can be collapsed into a single overflow label set.
+pub trait OverflowLabelSet {
+	/// Returns a copy of the label set with every unbounded label value replaced by [OVERFLOW_LABEL_VALUE].
+	fn overflow(&self) -> Self;
+}
+
+/// Label set that can identify the log file it came from.
+pub trait FileLabelSet {
+	/// Returns whether the label set has the same file labels.
+	fn belongs_to(&self, file: &FileLabels) -> bool;
+}
+
+/// Family whose series can be removed when they have not been updated for a while, or when their log file is gone.
+pub trait ExpiringFamily: Send + Sync {
+	/// Removes series that were last updated before `now - ttl`, and returns how many were removed.
+	fn remove_stale_series(&self, now: Instant, ttl: Duration) -> usize;
+	
+	/// Removes series that belong to the file labels, and returns how many were removed.
+	fn remove_file_series(&self, file: &FileLabels) -> usize;
+}
+
+/// Wraps a [Family] and limits how many label sets it can contain. Once the limit is reached,
+/// new label sets are replaced by their [OverflowLabelSet::overflow] variant, and the replacement
+/// is counted in a separate counter.
+///
+/// The time of the last update of each label set is tracked, so that stale series can be removed.
+pub struct BoundedFamily<S, M, C = fn() -> M> {
+	family: Family<S, M, C>,
+	series: Arc<Mutex<HashMap<S, Instant>>>,
+	max_series: usize,
+	dropped_series: Counter,
+}
+
+impl<S, M, C: Clone> Clone for BoundedFamily<S, M, C> {
+	fn clone(&self) -> Self {
+		BoundedFamily {
+			family: self.family.clone(),
+			series: Arc::clone(&self.series),
+			max_series: self.max_series,
+			dropped_series: self.dropped_series.clone(),
+		}
+	}
+}
+
+impl<S: Clone + Hash + Eq + OverflowLabelSet, M, C: MetricConstructor<M>> BoundedFamily<S, M, C> {
+	pub fn new(family: Family<S, M, C>, max_series: usize, dropped_series: Counter) -> Self {
+		BoundedFamily { family, series: Arc::new(Mutex::new(HashMap::new())), max_series, dropped_series }
+	}
+	
+	pub fn get_or_create(&self, label_set: &S) -> impl Deref<Target = M> + '_ {
+		let mut series = self.series.lock().unwrap_or_else(PoisonError::into_inner);
+		let now = Instant::now();
+		
+		if let Some(last_update) = series.get_mut(label_set) {
+			*last_update = now;
+			self.family.get_or_create(label_set)
+		} else if series.len() < self.max_series {
+			series.insert(label_set.clone(), now);
+			self.family.get_or_create(label_set)
+		} else {
+			let overflow = label_set.overflow();
+			
+			// Label sets without labels derived from log content are kept as they are, so nothing is dropped.
+			if overflow != *label_set {
+				self.dropped_series.inc();
+			}
+			
+			series.insert(overflow.clone(), now);
+			self.family.get_or_create(&overflow)
+		}
+	}
+}
+
+impl<S: Clone + Hash + Eq, M, C: MetricConstructor<M>> BoundedFamily<S, M, C> {
+	fn remove_series_where(&self, predicate: impl Fn(&S, &Instant) -> bool) -> usize {
+		let mut series = self.series.lock().unwrap_or_else(PoisonError::into_inner);
+		let count_before = series.len();
+		
+		series.retain(|label_set, last_update| {
+			let remove = predicate(label_set, last_update);
+			if remove {
+				self.family.remove(label_set);
+			}
+			!remove
+		});
+		
+		count_before - series.len()
+	}
+}
+
+impl<S, M, C> ExpiringFamily for BoundedFamily<S, M, C>
+where
+	S: Clone + Hash + Eq + FileLabelSet + Send + Sync,
+	M: Send + Sync,
+	C: MetricConstructor<M> + Send + Sync,
+{
+	fn remove_stale_series(&self, now: Instant, ttl: Duration) -> usize {
+		self.remove_series_where(|_, last_update| now.saturating_duration_since(*last_update) >= ttl)
+	}
+	
+	fn remove_file_series(&self, file: &FileLabels) -> usize {
+		self.remove_series_where(|label_set, _| label_set.belongs_to(file))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::time::{Duration, Instant};
+	
+	use prometheus_client::metrics::counter::Counter;
+	use prometheus_client::metrics::family::Family;
+	
+	use crate::metrics::{ErrorLabels, FileLabels, PatternLabels};
+	
+	use super::{BoundedFamily, ExpiringFamily, FileLabelSet, OVERFLOW_LABEL_VALUE, OverflowLabelSet};
+	
+	type Labels = [(&'static str, String); 1];
+	
+	impl OverflowLabelSet for Labels {
+		fn overflow(&self) -> Self {
+			[("path", OVERFLOW_LABEL_VALUE.to_string())]
+		}
+	}
+	
+	impl FileLabelSet for Labels {
+		fn belongs_to(&self, _file: &FileLabels) -> bool {
+			false
+		}
+	}
+	
+	fn labels(path: &str) -> Labels {
+		[("path", path.to_string())]
+	}
+	
+	#[test]
+	fn overflow_after_limit() {
+		let dropped_series = Counter::default();
+		let family = BoundedFamily::<Labels, Counter>::new(Family::default(), 2, dropped_series.clone());
+		
+		family.get_or_create(&labels("/a")).inc();
+		family.get_or_create(&labels("/b")).inc();
+		family.get_or_create(&labels("/a")).inc();
+		family.get_or_create(&labels("/c")).inc();
+		family.get_or_create(&labels("/d")).inc();
+		
+		assert_eq!(family.family.get_or_create(&labels("/a")).get(), 2);
+		assert_eq!(family.family.get_or_create(&labels("/b")).get(), 1);
+		assert_eq!(family.family.get_or_create(&labels(OVERFLOW_LABEL_VALUE)).get(), 2);
+		assert_eq!(dropped_series.get(), 2);
+	}
+	
+	#[test]
+	fn remove_stale_series() {
+		let family = BoundedFamily::<Labels, Counter>::new(Family::default(), 2, Counter::default());
+		
+		family.get_or_create(&labels("/a")).inc();
+		family.get_or_create(&labels("/b")).inc();
+		
+		assert_eq!(family.remove_stale_series(Instant::now(), Duration::from_secs(60)), 0);
+		assert_eq!(family.remove_stale_series(Instant::now() + Duration::from_secs(60), Duration::from_secs(60)), 2);
+		
+		// Removed series no longer count towards the limit, and start from zero.
+		family.get_or_create(&labels("/c")).inc();
+		family.get_or_create(&labels("/a")).inc();
+		assert_eq!(family.family.get_or_create(&labels("/a")).get(), 1);
+		assert_eq!(family.family.get_or_create(&labels(OVERFLOW_LABEL_VALUE)).get(), 0);
+	}
+	
+	#[test]
+	fn remove_file_series() {
+		let family = BoundedFamily::<ErrorLabels, Counter>::new(Family::default(), 4, Counter::default());
+		
+		let site_a = PatternLabels::new(vec![(String::from("site"), String::from("a"))]);
+		let site_b = PatternLabels::new(vec![(String::from("site"), String::from("b"))]);
+		let error_labels = |file: &str, level: &str, pattern_labels: &PatternLabels| ErrorLabels { file: file.to_string(), module: String::from("core"), level: level.to_string(), pattern_labels: pattern_labels.clone() };
+		
+		family.get_or_create(&error_labels("error", "warn", &site_a)).inc();
+		family.get_or_create(&error_labels("error", "error", &site_a)).inc();
+		family.get_or_create(&error_labels("error", "warn", &site_b)).inc();
+		family.get_or_create(&error_labels("other", "warn", &site_a)).inc();
+		
+		assert_eq!(family.remove_file_series(&FileLabels { file: String::from("error"), pattern_labels: site_a.clone() }), 2);
+		assert_eq!(family.family.get_or_create(&error_labels("error", "warn", &site_a)).get(), 0);
+		assert_eq!(family.family.get_or_create(&error_labels("error", "warn", &site_b)).get(), 1);
+		assert_eq!(family.family.get_or_create(&error_labels("other", "warn", &site_a)).get(), 1);
+	}
+}
@@ -0,0 +1,448 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::hash::Hash;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Context, Result};
+use prometheus_client::encoding::{EncodeLabelSet, LabelSetEncoder};
+use prometheus_client::metrics::counter::Counter;
+use prometheus_client::metrics::family::{Family, MetricConstructor};
+use prometheus_client::metrics::histogram::Histogram;
+use prometheus_client::registry::{Metric, Registry};
+
+use crate::logs::error_log_parser::is_message_code;
+use crate::metrics::bounded_family::{BoundedFamily, ExpiringFamily, FileLabelSet, OVERFLOW_LABEL_VALUE, OverflowLabelSet};
+use crate::route_rules::RouteRules;
+
+mod bounded_family;
+
+/// Names of labels that are set by the exporter, and so cannot be extracted from log file paths.
+pub const RESERVED_LABEL_NAMES: [&str; 10] = ["status", "status_class", "method", "protocol", "route", "module", "level", "code", "reason", "le"];
+
+/// Labels extracted from the path of a log file by the named capture groups of a regex file pattern.
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+pub struct PatternLabels(Arc<[(String, String)]>);
+
+impl PatternLabels {
+	pub fn new(labels: Vec<(String, String)>) -> Self {
+		PatternLabels(Arc::from(labels))
+	}
+}
+
+impl EncodeLabelSet for PatternLabels {
+	fn encode(&self, encoder: LabelSetEncoder) -> Result<(), fmt::Error> {
+		self.0.as_ref().encode(encoder)
+	}
+}
+
+#[derive(Clone, Debug, Eq, Hash, PartialEq, EncodeLabelSet)]
+pub struct FileLabels {
+	pub file: String,
+	#[prometheus(flatten)]
+	pub pattern_labels: PatternLabels,
+}
+
+#[derive(Clone, Debug, Eq, Hash, PartialEq, EncodeLabelSet)]
+pub struct RequestLabels {
+	pub file: String,
+	pub status: String,
+	pub status_class: &'static str,
+	pub method: &'static str,
+	pub protocol: &'static str,
+	#[prometheus(flatten)]
+	pub pattern_labels: PatternLabels,
+}
+
+#[derive(Clone, Debug, Eq, Hash, PartialEq, EncodeLabelSet)]
+pub struct RouteLabels {
+	pub file: String,
+	pub route: String,
+	#[prometheus(flatten)]
+	pub pattern_labels: PatternLabels,
+}
+
+#[derive(Clone, Debug, Eq, Hash, PartialEq, EncodeLabelSet)]
+pub struct RouteRequestLabels {
+	pub file: String,
+	pub route: String,
+	pub method: &'static str,
+	pub status_class: &'static str,
+	#[prometheus(flatten)]
+	pub pattern_labels: PatternLabels,
+}
+
+#[derive(Clone, Debug, Eq, Hash, PartialEq, EncodeLabelSet)]
+pub struct ErrorLabels {
+	pub file: String,
+	pub module: String,
+	pub level: String,
+	#[prometheus(flatten)]
+	pub pattern_labels: PatternLabels,
+}
+
+#[derive(Clone, Debug, Eq, Hash, PartialEq, EncodeLabelSet)]
+pub struct ErrorCodeLabels {
+	pub file: String,
+	pub code: String,
+	#[prometheus(flatten)]
+	pub pattern_labels: PatternLabels,
+}
+
+#[derive(Clone, Debug, Eq, Hash, PartialEq, EncodeLabelSet)]
+pub struct ParseErrorLabels {
+	pub file: String,
+	pub reason: &'static str,
+	#[prometheus(flatten)]
+	pub pattern_labels: PatternLabels,
+}
+
+#[derive(Clone, Debug, Eq, Hash, PartialEq, EncodeLabelSet)]
+struct DroppedSeriesLabels {
+	metric: &'static str,
+}
+
+/// File labels are derived from log file paths rather than log content, so they are kept like in all other label sets,
+/// and their number is bounded by the number of watched files.
+impl OverflowLabelSet for FileLabels {
+	fn overflow(&self) -> Self {
+		self.clone()
+	}
+}
+
+impl OverflowLabelSet for RequestLabels {
+	fn overflow(&self) -> Self {
+		RequestLabels {
+			file: self.file.clone(),
+			status: OVERFLOW_LABEL_VALUE.to_string(),
+			status_class: OVERFLOW_LABEL_VALUE,
+			method: OVERFLOW_LABEL_VALUE,
+			protocol: OVERFLOW_LABEL_VALUE,
+			pattern_labels: self.pattern_labels.clone(),
+		}
+	}
+}
+
+impl OverflowLabelSet for RouteLabels {
+	fn overflow(&self) -> Self {
+		RouteLabels { file: self.file.clone(), route: OVERFLOW_LABEL_VALUE.to_string(), pattern_labels: self.pattern_labels.clone() }
+	}
+}
+
+impl OverflowLabelSet for RouteRequestLabels {
+	fn overflow(&self) -> Self {
+		RouteRequestLabels {
+			file: self.file.clone(),
+			route: OVERFLOW_LABEL_VALUE.to_string(),
+			method: OVERFLOW_LABEL_VALUE,
+			status_class: OVERFLOW_LABEL_VALUE,
+			pattern_labels: self.pattern_labels.clone(),
+		}
+	}
+}
+
+impl OverflowLabelSet for ErrorLabels {
+	fn overflow(&self) -> Self {
+		ErrorLabels { file: self.file.clone(), module: OVERFLOW_LABEL_VALUE.to_string(), level: OVERFLOW_LABEL_VALUE.to_string(), pattern_labels: self.pattern_labels.clone() }
+	}
+}
+
+impl OverflowLabelSet for ErrorCodeLabels {
+	fn overflow(&self) -> Self {
+		ErrorCodeLabels { file: self.file.clone(), code: OVERFLOW_LABEL_VALUE.to_string(), pattern_labels: self.pattern_labels.clone() }
+	}
+}
+
+impl OverflowLabelSet for ParseErrorLabels {
+	fn overflow(&self) -> Self {
+		ParseErrorLabels { file: self.file.clone(), reason: OVERFLOW_LABEL_VALUE, pattern_labels: self.pattern_labels.clone() }
+	}
+}
+
+/// Implements [FileLabelSet] for label sets that contain the `file` label and the labels extracted from file patterns.
+macro_rules! impl_file_label_set {
+	($($label_set:ty),*) => {
+		$(
+			impl FileLabelSet for $label_set {
+				fn belongs_to(&self, file: &FileLabels) -> bool {
+					self.file == file.file && self.pattern_labels == file.pattern_labels
+				}
+			}
+		)*
+	};
+}
+
+impl_file_label_set!(FileLabels, RequestLabels, RouteLabels, RouteRequestLabels, ErrorLabels, ErrorCodeLabels, ParseErrorLabels);
+
+/// Default maximum number of label sets per metric.
+pub const DEFAULT_MAX_SERIES_PER_METRIC: usize = 10_000;
+
+/// Default histogram buckets for request durations, in seconds.
+pub const DEFAULT_REQUEST_DURATION_BUCKETS: [f64; 11] = [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+pub struct MetricsConfiguration {
+	pub request_duration_buckets: Vec<f64>,
+	pub response_size_buckets: Option<Vec<f64>>,
+	pub error_code_allowlist: Option<HashSet<String>>,
+	pub route_rules: Option<RouteRules>,
+	pub max_series_per_metric: usize,
+	pub series_ttl: Option<Duration>,
+}
+
+impl Default for MetricsConfiguration {
+	fn default() -> Self {
+		MetricsConfiguration {
+			request_duration_buckets: DEFAULT_REQUEST_DURATION_BUCKETS.to_vec(),
+			response_size_buckets: None,
+			error_code_allowlist: None,
+			route_rules: None,
+			max_series_per_metric: DEFAULT_MAX_SERIES_PER_METRIC,
+			series_ttl: None,
+		}
+	}
+}
+
+#[derive(Clone)]
+pub struct HistogramBuckets(Arc<[f64]>);
+
+impl MetricConstructor<Histogram> for HistogramBuckets {
+	fn new_metric(&self) -> Histogram {
+		Histogram::new(self.0.iter().copied())
+	}
+}
+
+/// Metrics labeled by normalized request path, which only exist if route rules are configured.
+#[derive(Clone)]
+pub struct RouteMetrics {
+	pub rules: Arc<RouteRules>,
+	pub requests_total: BoundedFamily<RouteRequestLabels, Counter>,
+	pub request_duration_seconds: BoundedFamily<RouteLabels, Histogram, HistogramBuckets>,
+}
+
+#[derive(Clone)]
+pub struct Metrics {
+	pub requests_total: BoundedFamily<RequestLabels, Counter>,
+	pub errors_total: BoundedFamily<ErrorLabels, Counter>,
+	pub access_log_parse_errors_total: BoundedFamily<ParseErrorLabels, Counter>,
+	pub malformed_requests_total: BoundedFamily<FileLabels, Counter>,
+	pub error_log_parse_errors_total: BoundedFamily<ParseErrorLabels, Counter>,
+	pub request_duration_seconds: BoundedFamily<FileLabels, Histogram, HistogramBuckets>,
+	pub response_bytes_total: BoundedFamily<FileLabels, Counter>,
+	pub response_size_bytes: Option<BoundedFamily<FileLabels, Histogram, HistogramBuckets>>,
+	pub error_codes_total: Option<BoundedFamily<ErrorCodeLabels, Counter>>,
+	pub log_rotations_total: BoundedFamily<FileLabels, Counter>,
+	error_code_allowlist: Arc<HashSet<String>>,
+	pub routes: Option<RouteMetrics>,
+	families: Arc<Vec<Box<dyn ExpiringFamily>>>,
+	series_ttl: Option<Duration>,
+}
+
+/// Registers metric families and limits the number of their label sets.
+struct MetricRegistrar<'a> {
+	registry: &'a mut Registry,
+	max_series: usize,
+	dropped_series_total: Family<DroppedSeriesLabels, Counter>,
+	families: Vec<Box<dyn ExpiringFamily>>,
+}
+
+impl MetricRegistrar<'_> {
+	fn register<S, M, C>(&mut self, name: &'static str, help: &str, family: Family<S, M, C>) -> BoundedFamily<S, M, C>
+	where
+		S: Clone + Hash + Eq + OverflowLabelSet + FileLabelSet + Send + Sync + 'static,
+		M: Send + Sync + 'static,
+		C: MetricConstructor<M> + Clone + Send + Sync + 'static,
+		Family<S, M, C>: Metric + Clone,
+	{
+		self.registry.register(name, help, family.clone());
+		
+		let dropped_series = self.dropped_series_total.get_or_create(&DroppedSeriesLabels { metric: name }).clone();
+		let family = BoundedFamily::new(family, self.max_series, dropped_series);
+		self.families.push(Box::new(family.clone()));
+		family
+	}
+}
+
+impl Metrics {
+	pub fn new(config: MetricsConfiguration) -> (Registry, Metrics) {
+		let mut registry = <Registry>::default();
+		
+		let dropped_series_total = Family::<DroppedSeriesLabels, Counter>::default();
+		registry.register("apache_exporter_dropped_series", "Number of observations whose labels were replaced with __overflow__ because the metric reached its maximum number of series", dropped_series_total.clone());
+		
+		let mut registrar = MetricRegistrar { registry: &mut registry, max_series: config.max_series_per_metric, dropped_series_total, families: Vec::new() };
+		let request_duration_buckets = HistogramBuckets(config.request_duration_buckets.into());
+		
+		let metrics = Metrics {
+			requests_total: registrar.register("apache_requests", "Number of received requests", Family::default()),
+			errors_total: registrar.register("apache_errors", "Number of logged errors", Family::default()),
+			access_log_parse_errors_total: registrar.register("apache_access_log_parse_errors", "Number of access log lines that could not be parsed", Family::default()),
+			malformed_requests_total: registrar.register("apache_malformed_requests", "Number of access log lines with a malformed request line", Family::default()),
+			error_log_parse_errors_total: registrar.register("apache_error_log_parse_errors", "Number of error log lines that could not be parsed", Family::default()),
+			request_duration_seconds: registrar.register("apache_request_duration_seconds", "Time taken to serve requests", Family::new_with_constructor(request_duration_buckets.clone())),
+			response_bytes_total: registrar.register("apache_response_bytes", "Number of bytes sent in responses", Family::default()),
+			response_size_bytes: config.response_size_buckets.map(|buckets| {
+				registrar.register("apache_response_size_bytes", "Size of responses in bytes", Family::new_with_constructor(HistogramBuckets(buckets.into())))
+			}),
+			error_codes_total: config.error_code_allowlist.as_ref().map(|_| {
+				registrar.register("apache_error_codes", "Number of logged errors by message code", Family::default())
+			}),
+			log_rotations_total: registrar.register("apache_log_rotations", "Number of times a log file was rotated, either by being replaced or truncated", Family::default()),
+			error_code_allowlist: Arc::new(config.error_code_allowlist.unwrap_or_default()),
+			routes: config.route_rules.map(|rules| RouteMetrics {
+				rules: Arc::new(rules),
+				requests_total: registrar.register("apache_route_requests", "Number of received requests by route", Family::default()),
+				request_duration_seconds: registrar.register("apache_route_request_duration_seconds", "Time taken to serve requests by route", Family::new_with_constructor(request_duration_buckets)),
+			}),
+			families: Arc::new(registrar.families),
+			series_ttl: config.series_ttl,
+		};
+		
+		(registry, metrics)
+	}
+	
+	/// Starts a task that periodically removes series which were not updated within the configured TTL.
+	pub fn start_expiring_stale_series(&self) {
+		if let Some(ttl) = self.series_ttl {
+			tokio::spawn(Self::expire_stale_series(Arc::clone(&self.families), ttl));
+		}
+	}
+	
+	async fn expire_stale_series(families: Arc<Vec<Box<dyn ExpiringFamily>>>, ttl: Duration) {
+		let mut interval = tokio::time::interval(ttl.min(Duration::from_secs(60)));
+		
+		loop {
+			interval.tick().await;
+			
+			let now = Instant::now();
+			let removed_series = families.iter().map(|family| family.remove_stale_series(now, ttl)).sum::<usize>();
+			
+			if removed_series > 0 {
+				println!("[Metrics] Removed {} stale series.", removed_series);
+			}
+		}
+	}
+	
+	/// Removes all series of a log file that is no longer watched, and returns how many were removed.
+	pub fn remove_file_series(&self, file: &FileLabels) -> usize {
+		self.families.iter().map(|family| family.remove_file_series(file)).sum()
+	}
+	
+	/// Returns the label for an error message code, or `other` if the code is not in the allowlist.
+	pub fn error_code_label(&self, code: &str) -> String {
+		if self.error_code_allowlist.contains(code) {
+			code.to_string()
+		} else {
+			String::from("other")
+		}
+	}
+}
+
+/// Parses a comma-separated list of histogram bucket upper bounds.
+pub fn parse_histogram_buckets(str: &str) -> Result<Vec<f64>> {
+	let mut buckets = Vec::new();
+	
+	for bucket in str.split(',').map(str::trim) {
+		buckets.push(bucket.parse::<f64>().with_context(|| format!("Invalid bucket: {}", bucket))?);
+	}
+	
+	validate_histogram_buckets(buckets)
+}
+
+/// Checks that histogram bucket upper bounds are finite and strictly increasing.
+pub fn validate_histogram_buckets(buckets: Vec<f64>) -> Result<Vec<f64>> {
+	if buckets.iter().any(|bucket| !bucket.is_finite()) {
+		bail!("Buckets must be finite");
+	}
+	
+	if buckets.windows(2).any(|pair| pair[0] >= pair[1]) {
+		bail!("Buckets are not in increasing order");
+	}
+	
+	Ok(buckets)
+}
+
+/// Parses a comma-separated list of error message codes, such as `AH01630,AH00124`.
+pub fn parse_error_code_allowlist(str: &str) -> Result<HashSet<String>> {
+	validate_error_code_allowlist(str.split(',').map(str::trim).filter(|code| !code.is_empty()))
+}
+
+/// Checks that all error message codes have the form `AHnnnnn`.
+pub fn validate_error_code_allowlist<'a>(codes: impl IntoIterator<Item = &'a str>) -> Result<HashSet<String>> {
+	let mut allowlist = HashSet::new();
+	
+	for code in codes {
+		if !is_message_code(code) {
+			bail!("Invalid error code: {}", code);
+		}
+		
+		allowlist.insert(code.to_string());
+	}
+	
+	Ok(allowlist)
+}
+
+#[cfg(test)]
+mod tests {
+	use prometheus_client::encoding::text::encode;
+	use prometheus_client::metrics::counter::Counter;
+	use prometheus_client::metrics::family::Family;
+	use prometheus_client::registry::Registry;
+	
+	use crate::metrics::bounded_family::OverflowLabelSet;
+	
+	use super::{FileLabels, parse_error_code_allowlist, parse_histogram_buckets, PatternLabels, RouteLabels};
+	
+	#[test]
+	fn valid_buckets() {
+		assert_eq!(parse_histogram_buckets("0.1, 0.5,1,10").unwrap(), vec![0.1, 0.5, 1.0, 10.0]);
+	}
+	
+	#[test]
+	fn invalid_bucket() {
+		assert!(matches!(parse_histogram_buckets("0.1,abc"), Err(err) if err.to_string() == "Invalid bucket: abc"));
+		assert!(matches!(parse_histogram_buckets(""), Err(err) if err.to_string() == "Invalid bucket: "));
+		assert!(matches!(parse_histogram_buckets("1,inf"), Err(err) if err.to_string() == "Buckets must be finite"));
+	}
+	
+	#[test]
+	fn buckets_not_increasing() {
+		assert!(matches!(parse_histogram_buckets("1,0.5"), Err(err) if err.to_string() == "Buckets are not in increasing order"));
+		assert!(matches!(parse_histogram_buckets("1,1"), Err(err) if err.to_string() == "Buckets are not in increasing order"));
+	}
+	
+	#[test]
+	fn valid_error_code_allowlist() {
+		let codes = parse_error_code_allowlist("AH01630, AH00124,").unwrap();
+		assert_eq!(codes.len(), 2);
+		assert!(codes.contains("AH01630"));
+		assert!(codes.contains("AH00124"));
+	}
+	
+	#[test]
+	fn invalid_error_code_allowlist() {
+		assert!(matches!(parse_error_code_allowlist("AH01630,AH1"), Err(err) if err.to_string() == "Invalid error code: AH1"));
+		assert!(matches!(parse_error_code_allowlist("01630"), Err(err) if err.to_string() == "Invalid error code: 01630"));
+	}
+	
+	#[test]
+	fn pattern_labels_are_encoded_last() {
+		let family = Family::<RouteLabels, Counter>::default();
+		let mut registry = Registry::default();
+		registry.register("routes", "", family.clone());
+		
+		let pattern_labels = PatternLabels::new(vec![(String::from("site"), String::from("example.com")), (String::from("env"), String::from("prod"))]);
+		family.get_or_create(&RouteLabels { file: String::from("access"), route: String::from("/"), pattern_labels }).inc();
+		family.get_or_create(&RouteLabels { file: String::from("legacy"), route: String::from("/"), pattern_labels: PatternLabels::default() }).inc();
+		
+		let mut output = String::new();
+		encode(&mut output, &registry).unwrap();
+		assert!(output.contains("routes_total{file=\"access\",route=\"/\",site=\"example.com\",env=\"prod\"} 1\n"));
+		assert!(output.contains("routes_total{file=\"legacy\",route=\"/\"} 1\n"));
+	}
+	
+	#[test]
+	fn file_labels_are_kept_on_overflow() {
+		let file_labels = FileLabels { file: String::from("access"), pattern_labels: PatternLabels::new(vec![(String::from("site"), String::from("example.com"))]) };
+		assert_eq!(file_labels.overflow(), file_labels);
+	}
+}
@@ -0,0 +1,89 @@
+use anyhow::{anyhow, Context, Result};
+use regex::Regex;
+
+/// Route label for paths that do not match any rule.
+pub const FALLBACK_ROUTE: &str = "other";
+
+/// Ordered list of rules that normalize request paths into routes, so that
+/// per-path metrics do not create a new series for every distinct URL.
+#[derive(Debug)]
+pub struct RouteRules {
+	rules: Vec<RouteRule>,
+}
+
+#[derive(Debug)]
+struct RouteRule {
+	pattern: Regex,
+	template: String,
+}
+
+impl RouteRules {
+	pub fn new() -> Self {
+		RouteRules { rules: Vec::new() }
+	}
+	
+	/// Adds a rule that maps paths matching the regular expression to the template. The template may refer to
+	/// capture groups using `$1` or `$name`.
+	pub fn add(&mut self, pattern: &str, template: &str) -> Result<()> {
+		let pattern = Regex::new(pattern).with_context(|| format!("Invalid route pattern: {}", pattern))?;
+		self.rules.push(RouteRule { pattern, template: template.to_string() });
+		Ok(())
+	}
+	
+	/// Parses rules in the form `<regex> -> <template>`, one per line. Empty lines are ignored.
+	pub fn parse(str: &str) -> Result<Self> {
+		let mut rules = RouteRules::new();
+		
+		for line in str.lines().map(str::trim).filter(|line| !line.is_empty()) {
+			let (pattern, template) = line.rsplit_once(" -> ").ok_or_else(|| anyhow!("Route rule is missing ' -> ': {}", line))?;
+			rules.add(pattern.trim(), template.trim())?;
+		}
+		
+		Ok(rules)
+	}
+	
+	/// Returns the route of the first rule that matches the path, or [FALLBACK_ROUTE].
+	pub fn route(&self, path: &str) -> String {
+		for rule in &self.rules {
+			if let Some(captures) = rule.pattern.captures(path) {
+				let mut route = String::new();
+				captures.expand(&rule.template, &mut route);
+				return route;
+			}
+		}
+		
+		String::from(FALLBACK_ROUTE)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::RouteRules;
+	
+	#[test]
+	fn first_matching_rule_wins() {
+		let rules = RouteRules::parse("^/user/\\d+$ -> /user/:id\n\n^/user/.*$ -> /user/*\n").unwrap();
+		assert_eq!(rules.route("/user/123"), "/user/:id");
+		assert_eq!(rules.route("/user/abc"), "/user/*");
+	}
+	
+	#[test]
+	fn fallback_route() {
+		let rules = RouteRules::parse("^/$ -> /").unwrap();
+		assert_eq!(rules.route("/"), "/");
+		assert_eq!(rules.route("/random"), "other");
+	}
+	
+	#[test]
+	fn capture_groups() {
+		let rules = RouteRules::parse("^/api/(?P<version>v[12])/.*$ -> /api/$version").unwrap();
+		assert_eq!(rules.route("/api/v2/users"), "/api/v2");
+		assert_eq!(rules.route("/api/v3/users"), "other");
+	}
+	
+	#[test]
+	fn invalid_rules() {
+		assert!(matches!(RouteRules::parse("^/user"), Err(err) if err.to_string() == "Route rule is missing ' -> ': ^/user"));
+		assert!(matches!(RouteRules::parse("^/user/(\\d+ -> /user"), Err(err) if err.to_string() == "Invalid route pattern: ^/user/(\\d+"));
+	}
+}
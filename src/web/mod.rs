@@ -1,4 +1,5 @@
-use std::net::SocketAddr;
+use std::io;
+use std::net::{SocketAddr, TcpListener};
 use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
@@ -9,36 +10,41 @@ use hyper::server::Builder;
 use hyper::server::conn::AddrIncoming;
 use hyper::service::{make_service_fn, service_fn};
 use prometheus_client::registry::Registry;
+use socket2::{Domain, Protocol, Socket, Type};
 
 mod metrics_endpoint;
 
 const MAX_BUFFER_SIZE: usize = 1024 * 32;
+const LISTEN_BACKLOG: i32 = 1024;
 
 pub struct WebServer {
 	builder: Builder<AddrIncoming>,
+	metrics_path: Arc<str>,
 }
 
 impl WebServer {
 	//noinspection HttpUrlsUsage
-	pub fn try_bind(addr: SocketAddr) -> anyhow::Result<WebServer> {
-		println!("[WebServer] Starting web server on {0} with metrics endpoint: http://{0}/metrics", addr);
+	pub fn try_bind(addr: SocketAddr, metrics_path: &str) -> anyhow::Result<WebServer> {
+		println!("[WebServer] Starting web server on {0} with metrics endpoint: http://{0}{1}", addr, metrics_path);
 		
-		let builder = Server::try_bind(&addr).with_context(|| format!("Could not bind to {}", addr))?;
+		let listener = bind_listener(addr).with_context(|| format!("Could not bind to {}", addr))?;
+		let builder = Server::from_tcp(listener).with_context(|| format!("Could not listen on {}", addr))?;
 		let builder = builder.tcp_keepalive(Some(Duration::from_secs(60)));
 		let builder = builder.http1_only(true);
 		let builder = builder.http1_keepalive(true);
 		let builder = builder.http1_max_buf_size(MAX_BUFFER_SIZE);
 		let builder = builder.http1_header_read_timeout(Duration::from_secs(10));
 		
-		Ok(WebServer { builder })
+		Ok(WebServer { builder, metrics_path: Arc::from(metrics_path) })
 	}
 	
-	pub async fn serve(self, metrics_registry: Mutex<Registry>) {
-		let metrics_registry = Arc::new(metrics_registry);
+	pub async fn serve(self, metrics_registry: Arc<Mutex<Registry>>) {
+		let metrics_path = self.metrics_path;
 		let service = make_service_fn(move |_| {
+			let metrics_path = Arc::clone(&metrics_path);
 			let metrics_registry = Arc::clone(&metrics_registry);
 			async move {
-				Ok::<_, Error>(service_fn(move |req| handle_request(req, Arc::clone(&metrics_registry))))
+				Ok::<_, Error>(service_fn(move |req| handle_request(req, Arc::clone(&metrics_path), Arc::clone(&metrics_registry))))
 			}
 		});
 		
@@ -48,10 +54,44 @@ impl WebServer {
 	}
 }
 
-async fn handle_request(req: Request<Body>, metrics_registry: Arc<Mutex<Registry>>) -> Result<Response<Body>> {
-	if req.method() == Method::GET && req.uri().path() == "/metrics" {
+/// Binds a listener to the address. IPv6 listeners only accept IPv6 connections, so that an IPv4 listener can use
+/// the same port, which is otherwise rejected on systems where IPv6 sockets also accept IPv4 connections by default.
+fn bind_listener(addr: SocketAddr) -> io::Result<TcpListener> {
+	let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+	
+	if addr.is_ipv6() {
+		socket.set_only_v6(true)?;
+	}
+	
+	#[cfg(not(windows))]
+	socket.set_reuse_address(true)?;
+	
+	socket.bind(&addr.into())?;
+	socket.listen(LISTEN_BACKLOG)?;
+	socket.set_nonblocking(true)?;
+	Ok(socket.into())
+}
+
+async fn handle_request(req: Request<Body>, metrics_path: Arc<str>, metrics_registry: Arc<Mutex<Registry>>) -> Result<Response<Body>> {
+	if req.method() == Method::GET && req.uri().path() == &*metrics_path {
 		metrics_endpoint::handle(Arc::clone(&metrics_registry)).await
 	} else {
 		Response::builder().status(StatusCode::NOT_FOUND).body(Body::empty())
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+	
+	use super::bind_listener;
+	
+	#[test]
+	fn bind_both_families_on_same_port() {
+		let ipv4_listener = bind_listener(SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0)).unwrap();
+		let port = ipv4_listener.local_addr().unwrap().port();
+		
+		let ipv6_listener = bind_listener(SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), port)).unwrap();
+		assert_eq!(ipv6_listener.local_addr().unwrap().port(), port);
+	}
+}